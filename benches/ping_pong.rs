@@ -0,0 +1,65 @@
+//! Ping-pong between two tasks over a pair of unbounded channels, comparing
+//! the single producer channel with the mpsc one.
+//!
+//! Run with `cargo bench --bench ping_pong`.
+
+use local_sync::{mpsc, spsc};
+use std::time::{Duration, Instant};
+
+const ROUNDS: u32 = 1_000_000;
+
+async fn spsc_ping_pong() -> Duration {
+    let (ping_tx, mut ping_rx) = spsc::unbounded::channel::<u32>();
+    let (pong_tx, mut pong_rx) = spsc::unbounded::channel::<u32>();
+    let echo = monoio::spawn(async move {
+        while let Some(n) = ping_rx.recv().await {
+            pong_tx.send(n).unwrap();
+        }
+    });
+    let start = Instant::now();
+    for i in 0..ROUNDS {
+        ping_tx.send(i).unwrap();
+        assert_eq!(pong_rx.recv().await, Some(i));
+    }
+    let elapsed = start.elapsed();
+    drop(ping_tx);
+    echo.await;
+    elapsed
+}
+
+async fn mpsc_ping_pong() -> Duration {
+    let (ping_tx, mut ping_rx) = mpsc::unbounded::channel::<u32>();
+    let (pong_tx, mut pong_rx) = mpsc::unbounded::channel::<u32>();
+    let echo = monoio::spawn(async move {
+        while let Some(n) = ping_rx.recv().await {
+            pong_tx.send(n).unwrap();
+        }
+    });
+    let start = Instant::now();
+    for i in 0..ROUNDS {
+        ping_tx.send(i).unwrap();
+        assert_eq!(pong_rx.recv().await, Some(i));
+    }
+    let elapsed = start.elapsed();
+    drop(ping_tx);
+    echo.await;
+    elapsed
+}
+
+fn report(name: &str, elapsed: Duration) {
+    println!(
+        "{:<16} {:>8.1} ns/round trip",
+        name,
+        elapsed.as_nanos() as f64 / ROUNDS as f64
+    );
+}
+
+#[monoio::main]
+async fn main() {
+    // warm up
+    spsc_ping_pong().await;
+    mpsc_ping_pong().await;
+
+    report("spsc::unbounded", spsc_ping_pong().await);
+    report("mpsc::unbounded", mpsc_ping_pong().await);
+}
@@ -0,0 +1,429 @@
+//! Queue of values which become receivable once their deadline is reached.
+//!
+//! The queue does not know about any timer, it waits for the earliest
+//! deadline with the [`Clock`] it is created with. Use a clock built on the
+//! runtime timer in production and a [`ManualClock`] in tests, which only
+//! moves forward when told to.
+
+use futures_lite::future::poll_fn;
+use std::{
+    cell::RefCell,
+    cmp::Reverse,
+    collections::{BTreeMap, BinaryHeap},
+    future::Future,
+    pin::Pin,
+    rc::Rc,
+    task::{Context, Poll, Waker},
+    time::{Duration, Instant},
+};
+
+/// Source of the current time and of sleeps for a [`DelayQueue`].
+///
+/// A clock on top of the monoio timer looks like this:
+///
+/// ```ignore
+/// struct MonoioClock;
+///
+/// impl Clock for MonoioClock {
+///     type Sleep = monoio::time::Sleep;
+///
+///     fn now(&self) -> Instant {
+///         Instant::now()
+///     }
+///
+///     fn sleep_until(&self, deadline: Instant) -> Self::Sleep {
+///         monoio::time::sleep_until(monoio::time::Instant::from_std(deadline))
+///     }
+/// }
+/// ```
+pub trait Clock {
+    type Sleep: Future<Output = ()>;
+
+    fn now(&self) -> Instant;
+
+    /// Returns a future which completes once `now` has reached `deadline`.
+    fn sleep_until(&self, deadline: Instant) -> Self::Sleep;
+}
+
+/// A clock which only moves forward with [`ManualClock::advance`].
+///
+/// Clones share the same time.
+#[derive(Clone)]
+pub struct ManualClock {
+    inner: Rc<RefCell<ManualInner>>,
+}
+
+struct ManualInner {
+    now: Instant,
+    /// Deadlines and wakers of the pending sleeps, by sleep id.
+    sleepers: BTreeMap<u64, (Instant, Waker)>,
+    next_id: u64,
+}
+
+/// Future returned by [`ManualClock::sleep_until`].
+pub struct ManualSleep {
+    inner: Rc<RefCell<ManualInner>>,
+    deadline: Instant,
+    id: u64,
+}
+
+impl ManualClock {
+    /// Creates a clock starting at the current time.
+    pub fn new() -> Self {
+        Self {
+            inner: Rc::new(RefCell::new(ManualInner {
+                now: Instant::now(),
+                sleepers: BTreeMap::new(),
+                next_id: 0,
+            })),
+        }
+    }
+
+    /// Moves the time forward, waking the sleeps which are now due.
+    pub fn advance(&self, duration: Duration) {
+        let mut inner = self.inner.borrow_mut();
+        inner.now += duration;
+        let now = inner.now;
+        let (due, waiting) = std::mem::take(&mut inner.sleepers)
+            .into_iter()
+            .partition::<BTreeMap<_, _>, _>(|(_, (deadline, _))| *deadline <= now);
+        inner.sleepers = waiting;
+        drop(inner);
+        for (_, waker) in due.into_values() {
+            waker.wake();
+        }
+    }
+}
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for ManualClock {
+    type Sleep = ManualSleep;
+
+    fn now(&self) -> Instant {
+        self.inner.borrow().now
+    }
+
+    fn sleep_until(&self, deadline: Instant) -> ManualSleep {
+        let mut inner = self.inner.borrow_mut();
+        inner.next_id += 1;
+        ManualSleep {
+            inner: self.inner.clone(),
+            deadline,
+            id: inner.next_id,
+        }
+    }
+}
+
+impl Future for ManualSleep {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let mut inner = self.inner.borrow_mut();
+        if inner.now >= self.deadline {
+            inner.sleepers.remove(&self.id);
+            return Poll::Ready(());
+        }
+        // one slot per sleep, however often it is polled
+        match inner.sleepers.get_mut(&self.id) {
+            Some((_, waker)) => {
+                if !waker.will_wake(cx.waker()) {
+                    *waker = cx.waker().clone();
+                }
+            }
+            None => {
+                inner
+                    .sleepers
+                    .insert(self.id, (self.deadline, cx.waker().clone()));
+            }
+        }
+        Poll::Pending
+    }
+}
+
+impl Drop for ManualSleep {
+    fn drop(&mut self) {
+        let waker = self.inner.borrow_mut().sleepers.remove(&self.id);
+        drop(waker);
+    }
+}
+
+/// Identifies a value in a [`DelayQueue`]. It becomes invalid once the value
+/// has been received or removed.
+#[derive(PartialEq, Eq, Hash, Clone, Copy, Debug)]
+pub struct Key {
+    index: usize,
+    id: u64,
+}
+
+pub struct DelayQueue<T, C: Clock> {
+    clock: C,
+    entries: Vec<Option<Entry<T>>>,
+    /// Indexes of free entries.
+    free: Vec<usize>,
+    len: usize,
+    /// Deadlines with the index and version of their entry. Items whose entry
+    /// has been removed or reset are skipped when they come up, or dropped
+    /// all at once when they outnumber the values, see `compact`.
+    heap: BinaryHeap<Reverse<(Instant, usize, u64)>>,
+    /// Counter for key ids and entry versions.
+    next_id: u64,
+    sleep: Option<(Instant, Pin<Box<C::Sleep>>)>,
+    waker: Option<Waker>,
+}
+
+struct Entry<T> {
+    value: T,
+    deadline: Instant,
+    id: u64,
+    version: u64,
+}
+
+impl<T, C: Clock> DelayQueue<T, C> {
+    pub fn new(clock: C) -> Self {
+        Self {
+            clock,
+            entries: Vec::new(),
+            free: Vec::new(),
+            len: 0,
+            heap: BinaryHeap::new(),
+            next_id: 0,
+            sleep: None,
+            waker: None,
+        }
+    }
+
+    fn next_id(&mut self) -> u64 {
+        self.next_id += 1;
+        self.next_id
+    }
+
+    fn schedule(&mut self, index: usize, deadline: Instant, version: u64) {
+        self.heap.push(Reverse((deadline, index, version)));
+        // the receiving task may sleep until a later deadline
+        if self
+            .sleep
+            .as_ref()
+            .map(|(at, _)| deadline < *at)
+            .unwrap_or(true)
+        {
+            if let Some(waker) = self.waker.take() {
+                waker.wake();
+            }
+        }
+    }
+
+    /// Drops the heap items of removed and reset entries once they outnumber
+    /// the values, so the heap stays within twice the length of the queue.
+    fn compact(&mut self) {
+        // every value has exactly one live item
+        if self.heap.len() - self.len <= self.len {
+            return;
+        }
+        let entries = &self.entries;
+        self.heap.retain(|Reverse((_, index, version))| {
+            matches!(&entries[*index], Some(entry) if entry.version == *version)
+        });
+    }
+
+    fn entry(&self, key: Key) -> Option<&Entry<T>> {
+        match self.entries.get(key.index) {
+            Some(Some(entry)) if entry.id == key.id => Some(entry),
+            _ => None,
+        }
+    }
+
+    /// Inserts a value which becomes receivable at `deadline`.
+    pub fn insert(&mut self, value: T, deadline: Instant) -> Key {
+        let id = self.next_id();
+        let index = self.free.pop().unwrap_or(self.entries.len());
+        let entry = Entry {
+            value,
+            deadline,
+            id,
+            version: id,
+        };
+        if index == self.entries.len() {
+            self.entries.push(Some(entry));
+        } else {
+            self.entries[index] = Some(entry);
+        }
+        self.len += 1;
+        self.schedule(index, deadline, id);
+        Key { index, id }
+    }
+
+    /// Removes the value of `key` before its deadline. Returns `None` if the
+    /// value has already been received or removed.
+    pub fn remove(&mut self, key: Key) -> Option<T> {
+        self.entry(key)?;
+        let entry = self.entries[key.index].take().unwrap();
+        self.free.push(key.index);
+        self.len -= 1;
+        self.compact();
+        Some(entry.value)
+    }
+
+    /// Changes the deadline of the value of `key`. Returns `false` if the
+    /// value has already been received or removed.
+    pub fn reset(&mut self, key: Key, deadline: Instant) -> bool {
+        if self.entry(key).is_none() {
+            return false;
+        }
+        let version = self.next_id();
+        let entry = self.entries[key.index].as_mut().unwrap();
+        entry.deadline = deadline;
+        entry.version = version;
+        self.schedule(key.index, deadline, version);
+        self.compact();
+        true
+    }
+
+    /// Returns the deadline of the value of `key`.
+    pub fn deadline(&self, key: Key) -> Option<Instant> {
+        self.entry(key).map(|entry| entry.deadline)
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Receives the next value whose deadline has been reached, values are
+    /// received in deadline order.
+    ///
+    /// Returns `None` if the queue is empty.
+    pub async fn recv(&mut self) -> Option<T> {
+        poll_fn(|cx| self.poll_expired(cx)).await
+    }
+
+    pub fn poll_expired(&mut self, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        loop {
+            // skip the items of removed and reset entries
+            let (deadline, index) = match self.heap.peek() {
+                Some(Reverse((deadline, index, version))) => match &self.entries[*index] {
+                    Some(entry) if entry.version == *version => (*deadline, *index),
+                    _ => {
+                        self.heap.pop();
+                        continue;
+                    }
+                },
+                None => {
+                    self.sleep = None;
+                    return Poll::Ready(None);
+                }
+            };
+
+            if deadline <= self.clock.now() {
+                self.heap.pop();
+                let entry = self.entries[index].take().unwrap();
+                self.free.push(index);
+                self.len -= 1;
+                return Poll::Ready(Some(entry.value));
+            }
+
+            if self.sleep.as_ref().map(|(at, _)| *at) != Some(deadline) {
+                self.sleep = Some((deadline, Box::pin(self.clock.sleep_until(deadline))));
+            }
+            let (_, sleep) = self.sleep.as_mut().unwrap();
+            match sleep.as_mut().poll(cx) {
+                // check the clock again
+                Poll::Ready(()) => self.sleep = None,
+                Poll::Pending => {
+                    self.waker = Some(cx.waker().clone());
+                    return Poll::Pending;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Clock, DelayQueue, ManualClock};
+    use futures_lite::future::poll_once;
+    use std::time::Duration;
+
+    #[monoio::test]
+    async fn test_delay_queue() {
+        let clock = ManualClock::new();
+        let start = clock.now();
+        let mut queue = DelayQueue::new(clock.clone());
+        let secs = |n| start + Duration::from_secs(n);
+        queue.insert("c", secs(3));
+        let a = queue.insert("a", secs(1));
+        let b = queue.insert("b", secs(2));
+        let removed = queue.insert("removed", secs(1));
+        assert_eq!(queue.remove(removed), Some("removed"));
+        assert_eq!(queue.remove(removed), None);
+        // "a" now comes last
+        assert!(queue.reset(a, secs(4)));
+        assert_eq!(queue.deadline(b), Some(secs(2)));
+        assert_eq!(queue.len(), 3);
+
+        assert!(poll_once(queue.recv()).await.is_none());
+        clock.advance(Duration::from_secs(2));
+        assert_eq!(queue.recv().await, Some("b"));
+        assert!(!queue.reset(b, secs(5)));
+
+        let clock2 = clock.clone();
+        let join = monoio::spawn(async move {
+            clock2.advance(Duration::from_secs(2));
+        });
+        assert_eq!(queue.recv().await, Some("c"));
+        assert_eq!(queue.recv().await, Some("a"));
+        join.await;
+        assert_eq!(queue.recv().await, None);
+        assert!(queue.is_empty());
+    }
+
+    #[monoio::test]
+    async fn test_delay_queue_insert_earlier() {
+        let clock = ManualClock::new();
+        let start = clock.now();
+        let mut queue = DelayQueue::new(clock.clone());
+        queue.insert(2, start + Duration::from_secs(10));
+        // the queue sleeps until the later deadline
+        assert!(poll_once(queue.recv()).await.is_none());
+        queue.insert(1, start + Duration::from_secs(1));
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(queue.recv().await, Some(1));
+        assert_eq!(queue.len(), 1);
+    }
+
+    #[monoio::test]
+    async fn test_delay_queue_compact() {
+        let clock = ManualClock::new();
+        let start = clock.now();
+        let mut queue = DelayQueue::new(clock.clone());
+        let key = queue.insert(0, start + Duration::from_secs(1));
+        queue.insert(1, start + Duration::from_secs(2));
+        for secs in 3..100 {
+            assert!(queue.reset(key, start + Duration::from_secs(secs)));
+        }
+        assert!(queue.heap.len() <= 2 * queue.len());
+        assert_eq!(queue.remove(key), Some(0));
+        assert!(queue.heap.len() <= 2 * queue.len());
+        clock.advance(Duration::from_secs(2));
+        assert_eq!(queue.recv().await, Some(1));
+    }
+
+    #[monoio::test]
+    async fn test_manual_sleep_registers_once() {
+        let clock = ManualClock::new();
+        let mut sleep = Box::pin(clock.sleep_until(clock.now() + Duration::from_secs(1)));
+        for _ in 0..3 {
+            assert!(poll_once(sleep.as_mut()).await.is_none());
+        }
+        assert_eq!(clock.inner.borrow().sleepers.len(), 1);
+        drop(sleep);
+        assert!(clock.inner.borrow().sleepers.is_empty());
+    }
+}
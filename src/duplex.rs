@@ -0,0 +1,196 @@
+//! Connected pair of endpoints which can both send and receive.
+//!
+//! Each direction is a bounded mpsc channel. Dropping an end closes both
+//! directions: the other end receives the values already sent and then
+//! `None`, and its sends fail.
+
+use crate::mpsc::{bounded, SendError, TryRecvError};
+use std::{
+    rc::Rc,
+    task::{Context, Poll},
+};
+
+/// An endpoint sending `S` and receiving `R`.
+pub struct End<S, R> {
+    tx: SendHalf<S>,
+    rx: RecvHalf<R>,
+}
+
+/// The sending half of an [`End`].
+pub struct SendHalf<T> {
+    tx: bounded::Tx<T>,
+    /// Shared by the halves of the same end.
+    end: Rc<()>,
+}
+
+/// The receiving half of an [`End`].
+pub struct RecvHalf<T> {
+    rx: bounded::Rx<T>,
+    end: Rc<()>,
+}
+
+/// Creates a connected pair of endpoints, each direction queues up to
+/// `capacity` values.
+pub fn channel<A, B>(capacity: usize) -> (End<A, B>, End<B, A>) {
+    let (a_tx, a_rx) = bounded::channel(capacity);
+    let (b_tx, b_rx) = bounded::channel(capacity);
+    (End::new(a_tx, b_rx), End::new(b_tx, a_rx))
+}
+
+impl<S, R> End<S, R> {
+    fn new(tx: bounded::Tx<S>, rx: bounded::Rx<R>) -> Self {
+        let end = Rc::new(());
+        Self {
+            tx: SendHalf {
+                tx,
+                end: end.clone(),
+            },
+            rx: RecvHalf { rx, end },
+        }
+    }
+
+    pub async fn send(&self, value: S) -> Result<(), SendError<S>> {
+        self.tx.send(value).await
+    }
+
+    pub async fn recv(&mut self) -> Option<R> {
+        self.rx.recv().await
+    }
+
+    pub fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<Option<R>> {
+        self.rx.poll_recv(cx)
+    }
+
+    pub fn try_recv(&mut self) -> Result<R, TryRecvError> {
+        self.rx.try_recv()
+    }
+
+    /// Closes the sending direction. The other end receives the values
+    /// already sent and then `None`, while this end can still receive.
+    pub fn close_send(&self) {
+        self.tx.close()
+    }
+
+    /// Closes the receiving direction, the sends of the other end fail.
+    /// Values already sent can still be received.
+    pub fn close_recv(&self) {
+        self.rx.close()
+    }
+
+    /// Splits the end into halves which can be used by different tasks.
+    pub fn split(self) -> (SendHalf<S>, RecvHalf<R>) {
+        (self.tx, self.rx)
+    }
+
+    /// Joins halves split from the same end back together.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the halves were not split from the same end.
+    pub fn unsplit(tx: SendHalf<S>, rx: RecvHalf<R>) -> Self {
+        assert!(
+            tx.is_pair_of(&rx),
+            "unrelated halves passed to End::unsplit"
+        );
+        Self { tx, rx }
+    }
+}
+
+impl<T> SendHalf<T> {
+    pub async fn send(&self, value: T) -> Result<(), SendError<T>> {
+        self.tx.send(value).await
+    }
+
+    pub fn close(&self) {
+        self.tx.close()
+    }
+
+    /// Returns `true` if the other end stopped receiving.
+    pub fn is_closed(&self) -> bool {
+        self.tx.is_closed()
+    }
+
+    /// Completes when the other end stopped receiving.
+    pub async fn closed(&self) {
+        self.tx.closed().await
+    }
+
+    /// Returns `true` if both halves were split from the same end.
+    pub fn is_pair_of<R>(&self, other: &RecvHalf<R>) -> bool {
+        Rc::ptr_eq(&self.end, &other.end)
+    }
+}
+
+impl<T> RecvHalf<T> {
+    pub async fn recv(&mut self) -> Option<T> {
+        self.rx.recv().await
+    }
+
+    pub fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        self.rx.poll_recv(cx)
+    }
+
+    pub fn try_recv(&mut self) -> Result<T, TryRecvError> {
+        self.rx.try_recv()
+    }
+
+    pub fn close(&self) {
+        self.rx.close()
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.rx.is_closed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{channel, End};
+    use crate::mpsc::SendError;
+
+    #[monoio::test]
+    async fn test_duplex() {
+        let (mut left, right) = channel::<u32, &str>(1);
+        let (tx, mut rx) = right.split();
+        let join = monoio::spawn(async move {
+            while let Some(n) = rx.recv().await {
+                tx.send(if n % 2 == 0 { "even" } else { "odd" })
+                    .await
+                    .unwrap();
+            }
+            // the other direction is still open
+            tx.send("done").await.unwrap();
+        });
+        for n in 0..3 {
+            left.send(n).await.unwrap();
+            let expected = if n % 2 == 0 { "even" } else { "odd" };
+            assert_eq!(left.recv().await, Some(expected));
+        }
+        left.close_send();
+        assert_eq!(left.send(3).await, Err(SendError::RxClosed(3)));
+        assert_eq!(left.recv().await, Some("done"));
+        join.await;
+        assert_eq!(left.recv().await, None);
+    }
+
+    #[monoio::test]
+    async fn test_duplex_drop() {
+        let (left, mut right) = channel::<u32, u32>(2);
+        left.send(1).await.unwrap();
+        let (tx, rx) = left.split();
+        let left = End::unsplit(tx, rx);
+        drop(left);
+        assert_eq!(right.recv().await, Some(1));
+        assert_eq!(right.recv().await, None);
+        assert!(right.send(2).await.is_err());
+    }
+
+    #[test]
+    #[should_panic(expected = "unrelated halves")]
+    fn test_duplex_unsplit_unrelated() {
+        let (left, right) = channel::<u32, u32>(1);
+        let (tx, _) = left.split();
+        let (_, rx) = right.split();
+        let _ = End::unsplit(tx, rx);
+    }
+}
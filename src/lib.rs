@@ -5,16 +5,34 @@
 
 // shared basic data structure
 mod linked_list;
+mod notify;
 mod wake_list;
 
 // Semaphore
 pub mod semaphore;
 // BoundedChannel and UnboundedChannel
 pub mod mpsc;
+// Single producer BoundedChannel and UnboundedChannel
+pub mod spsc;
 
 // OneshotChannel
 pub mod oneshot;
 
+// Request/response channel
+pub mod rpc;
+
+// Connected pair of endpoints
+pub mod duplex;
+
+// In-memory byte pipe
+pub mod pipe;
+
+// Topic based publish/subscribe bus
+pub mod pubsub;
+
+// DelayQueue
+pub mod delay_queue;
+
 // OnceCell
 mod once_cell;
 pub use once_cell::{OnceCell, SetError};
@@ -0,0 +1,385 @@
+//! Bounded channel with acknowledged delivery.
+//!
+//! The receiver gets each value wrapped in a [`Delivery`], which must be
+//! acknowledged with [`Delivery::ack`]. A delivery which is dropped without
+//! being acknowledged, e.g. because the handler panicked or was cancelled,
+//! puts the value back to the front of the queue so it is received again.
+//!
+//! A value keeps its slot until it is acknowledged. Values which have been
+//! delivered `max_attempts` times without being acknowledged are moved to a
+//! dead letter receiver instead, see [`channel_with_dead_letter`].
+
+use super::{
+    block::Queue,
+    chan::{SendError, TryRecvError},
+    unbounded,
+};
+use crate::semaphore::Inner;
+use futures_lite::future::poll_fn;
+use std::{
+    cell::{Cell, RefCell},
+    ops::{Deref, DerefMut},
+    rc::Rc,
+    task::{Context, Poll, Waker},
+};
+
+pub struct Tx<T> {
+    chan: Rc<Chan<T>>,
+}
+
+pub struct Rx<T> {
+    chan: Rc<Chan<T>>,
+}
+
+/// A received value which has not been acknowledged yet.
+pub struct Delivery<T> {
+    chan: Rc<Chan<T>>,
+    value: Option<T>,
+    attempts: u32,
+}
+
+/// Creates a channel which redelivers unacknowledged values without limit.
+pub fn channel<T>(buffer: usize) -> (Tx<T>, Rx<T>) {
+    new_channel(buffer, u32::MAX, None)
+}
+
+/// Creates a channel which moves values to the returned dead letter receiver
+/// once they have been delivered `max_attempts` times without being
+/// acknowledged.
+///
+/// # Panics
+///
+/// Panics if `max_attempts` is 0.
+pub fn channel_with_dead_letter<T>(
+    buffer: usize,
+    max_attempts: u32,
+) -> (Tx<T>, Rx<T>, unbounded::Rx<T>) {
+    assert!(max_attempts > 0, "max_attempts must be greater than 0");
+    let (dead_tx, dead_rx) = unbounded::channel();
+    let (tx, rx) = new_channel(buffer, max_attempts, Some(dead_tx));
+    (tx, rx, dead_rx)
+}
+
+fn new_channel<T>(
+    buffer: usize,
+    max_attempts: u32,
+    dead_letter: Option<unbounded::Tx<T>>,
+) -> (Tx<T>, Rx<T>) {
+    let chan = Rc::new(Chan {
+        queue: RefCell::new(Queue::new()),
+        semaphore: Inner::new(buffer),
+        rx_waker: RefCell::new(None),
+        tx_count: Cell::new(1),
+        rx_alive: Cell::new(true),
+        in_flight: Cell::new(0),
+        max_attempts,
+        dead_letter,
+    });
+    let tx = Tx { chan: chan.clone() };
+    let rx = Rx { chan };
+    (tx, rx)
+}
+
+struct Chan<T> {
+    /// Queued values with the number of times they have been delivered.
+    queue: RefCell<Queue<(T, u32)>>,
+    semaphore: Inner,
+    rx_waker: RefCell<Option<Waker>>,
+    tx_count: Cell<usize>,
+    rx_alive: Cell<bool>,
+    /// Number of deliveries which have been neither acknowledged nor dropped.
+    in_flight: Cell<usize>,
+    max_attempts: u32,
+    dead_letter: Option<unbounded::Tx<T>>,
+}
+
+impl<T> Chan<T> {
+    fn close(&self) {
+        self.semaphore.close();
+        self.wake_receiver();
+    }
+
+    fn wake_receiver(&self) {
+        if let Some(w) = self.rx_waker.take() {
+            w.wake();
+        }
+    }
+
+    fn is_disconnected(&self) -> bool {
+        self.tx_count.get() == 0 || self.semaphore.is_closed()
+    }
+
+    /// A delivery has been acknowledged or given up on, free its slot.
+    fn finish(&self) {
+        self.in_flight.set(self.in_flight.get() - 1);
+        self.semaphore.release(1);
+        // the receiver may be waiting for the last delivery before it ends
+        if self.in_flight.get() == 0 && self.is_disconnected() {
+            self.wake_receiver();
+        }
+    }
+
+    /// A delivery has been dropped without being acknowledged.
+    fn requeue(&self, value: T, attempts: u32) {
+        if !self.rx_alive.get() {
+            drop(value);
+            self.finish();
+            return;
+        }
+        if attempts >= self.max_attempts {
+            if let Some(tx) = &self.dead_letter {
+                let _ = tx.send(value);
+            }
+            self.finish();
+            return;
+        }
+        self.in_flight.set(self.in_flight.get() - 1);
+        // the value still holds its permit
+        unsafe {
+            self.queue
+                .borrow_mut()
+                .push_front_unchecked((value, attempts))
+        };
+        self.wake_receiver();
+    }
+}
+
+impl<T> Drop for Chan<T> {
+    fn drop(&mut self) {
+        let mut queue = self.queue.borrow_mut();
+        while !queue.is_empty() {
+            drop(unsafe { queue.pop_unchecked() });
+        }
+        unsafe { queue.free_blocks() }
+    }
+}
+
+impl<T> Tx<T> {
+    pub async fn send(&self, value: T) -> Result<(), SendError<T>> {
+        // acquire semaphore first
+        if self.chan.semaphore.acquire(1).await.is_err() {
+            return Err(SendError::RxClosed(value));
+        }
+        unsafe { self.chan.queue.borrow_mut().push_unchecked((value, 0)) };
+        self.chan.wake_receiver();
+        Ok(())
+    }
+
+    pub fn close(&self) {
+        self.chan.close()
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.chan.semaphore.is_closed()
+    }
+
+    pub fn same_channel(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.chan, &other.chan)
+    }
+
+    pub fn hint(&self) -> usize {
+        self.chan.queue.borrow().len()
+    }
+}
+
+impl<T> Clone for Tx<T> {
+    fn clone(&self) -> Self {
+        self.chan.tx_count.set(self.chan.tx_count.get() + 1);
+        Self {
+            chan: self.chan.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Tx<T> {
+    fn drop(&mut self) {
+        let cnt = self.chan.tx_count.get();
+        self.chan.tx_count.set(cnt - 1);
+
+        if cnt == 1 {
+            self.chan.close();
+        }
+    }
+}
+
+impl<T> Rx<T> {
+    /// Receives the next value. Values which have not been acknowledged are
+    /// received again before the others.
+    ///
+    /// Returns `None` once the channel is closed, empty and no delivery is
+    /// left which may put its value back.
+    pub async fn recv(&mut self) -> Option<Delivery<T>> {
+        poll_fn(|cx| self.poll_recv(cx)).await
+    }
+
+    pub fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<Option<Delivery<T>>> {
+        match self.try_recv() {
+            Ok(val) => return Poll::Ready(Some(val)),
+            Err(TryRecvError::Empty) => {}
+            Err(_) => return Poll::Ready(None),
+        }
+        let mut borrowed = self.chan.rx_waker.borrow_mut();
+        match borrowed.as_mut() {
+            Some(inner) => {
+                if !inner.will_wake(cx.waker()) {
+                    *inner = cx.waker().clone();
+                }
+            }
+            None => {
+                *borrowed = Some(cx.waker().clone());
+            }
+        }
+        Poll::Pending
+    }
+
+    pub fn try_recv(&mut self) -> Result<Delivery<T>, TryRecvError> {
+        let mut queue = self.chan.queue.borrow_mut();
+        if !queue.is_empty() {
+            let (value, attempts) = unsafe { queue.pop_unchecked() };
+            self.chan.in_flight.set(self.chan.in_flight.get() + 1);
+            return Ok(Delivery {
+                chan: self.chan.clone(),
+                value: Some(value),
+                attempts: attempts + 1,
+            });
+        }
+        if self.chan.is_disconnected() && self.chan.in_flight.get() == 0 {
+            Err(TryRecvError::Disconnected)
+        } else {
+            Err(TryRecvError::Empty)
+        }
+    }
+
+    /// Returns the number of deliveries which have not been acknowledged or
+    /// dropped yet.
+    pub fn in_flight(&self) -> usize {
+        self.chan.in_flight.get()
+    }
+
+    /// Closes the channel without dropping the receiver. Values already
+    /// queued can still be received.
+    pub fn close(&self) {
+        self.chan.close()
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.chan.semaphore.is_closed()
+    }
+
+    pub fn hint(&self) -> usize {
+        self.chan.queue.borrow().len()
+    }
+}
+
+impl<T> Drop for Rx<T> {
+    fn drop(&mut self) {
+        self.chan.rx_alive.set(false);
+        // close the channel, this will make tx send await return.
+        self.chan.close();
+        // consume all elements
+        let mut queue = self.chan.queue.borrow_mut();
+        let len = queue.len();
+        while !queue.is_empty() {
+            drop(unsafe { queue.pop_unchecked() });
+        }
+        self.chan.semaphore.release(len);
+    }
+}
+
+impl<T> Delivery<T> {
+    /// Acknowledges the delivery, freeing the slot of the value.
+    pub fn ack(mut self) -> T {
+        let value = self.value.take().expect("delivery without value");
+        self.chan.finish();
+        value
+    }
+
+    /// Puts the value back to the front of the queue, or moves it to the dead
+    /// letter receiver if it has been delivered too many times. This is the
+    /// same as dropping the delivery.
+    pub fn nack(self) {}
+
+    /// Returns how many times the value has been delivered, including this
+    /// time.
+    pub fn attempts(&self) -> u32 {
+        self.attempts
+    }
+}
+
+impl<T> Deref for Delivery<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.value.as_ref().expect("delivery without value")
+    }
+}
+
+impl<T> DerefMut for Delivery<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.value.as_mut().expect("delivery without value")
+    }
+}
+
+impl<T> Drop for Delivery<T> {
+    fn drop(&mut self) {
+        if let Some(value) = self.value.take() {
+            self.chan.requeue(value, self.attempts);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{channel, channel_with_dead_letter};
+
+    #[monoio::test]
+    async fn test_ack_channel() {
+        let (tx, mut rx) = channel(2);
+        tx.send(1).await.unwrap();
+        tx.send(2).await.unwrap();
+
+        let delivery = rx.recv().await.unwrap();
+        assert_eq!(*delivery, 1);
+        assert_eq!(delivery.attempts(), 1);
+        // not acknowledged, received again first
+        drop(delivery);
+        let delivery = rx.recv().await.unwrap();
+        assert_eq!(*delivery, 1);
+        assert_eq!(delivery.attempts(), 2);
+        assert_eq!(delivery.ack(), 1);
+
+        // the slot is only freed on ack
+        let delivery = rx.recv().await.unwrap();
+        let tx2 = tx.clone();
+        let join = monoio::spawn(async move {
+            tx2.send(3).await.unwrap();
+            tx2.send(4).await.unwrap();
+        });
+        assert_eq!(rx.recv().await.unwrap().ack(), 3);
+        assert_eq!(rx.in_flight(), 1);
+        assert_eq!(delivery.ack(), 2);
+        join.await;
+
+        // the receiver does not end while a delivery may come back
+        drop(tx);
+        let delivery = rx.recv().await.unwrap();
+        assert!(rx.try_recv().is_err());
+        delivery.nack();
+        assert_eq!(rx.recv().await.unwrap().ack(), 4);
+        assert!(rx.recv().await.is_none());
+    }
+
+    #[monoio::test]
+    async fn test_ack_channel_dead_letter() {
+        let (tx, mut rx, mut dead) = channel_with_dead_letter(1, 2);
+        tx.send("poison").await.unwrap();
+        rx.recv().await.unwrap().nack();
+        rx.recv().await.unwrap().nack();
+        assert_eq!(dead.recv().await, Some("poison"));
+        // the slot has been freed
+        tx.send("ok").await.unwrap();
+        assert_eq!(rx.recv().await.unwrap().ack(), "ok");
+        drop(tx);
+        assert!(rx.recv().await.is_none());
+    }
+}
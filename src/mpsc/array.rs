@@ -0,0 +1,266 @@
+//! Bounded channel with a fixed, inline ring buffer.
+//!
+//! Items are stored in a `[MaybeUninit<T>; N]` inside the shared allocation
+//! and capacity is tracked with a plain length counter, so there are no
+//! blocks to link and no semaphore wait list to maintain.
+
+use super::chan::{SendError, TryRecvError};
+use futures_lite::future::poll_fn;
+use std::{
+    cell::{Cell, RefCell, UnsafeCell},
+    mem::MaybeUninit,
+    rc::Rc,
+    task::{Context, Poll, Waker},
+};
+
+pub struct Tx<T, const N: usize> {
+    chan: Rc<Chan<T, N>>,
+}
+
+pub struct Rx<T, const N: usize> {
+    chan: Rc<Chan<T, N>>,
+}
+
+pub fn channel<T, const N: usize>() -> (Tx<T, N>, Rx<T, N>) {
+    assert!(N > 0, "array channel capacity must be greater than 0");
+    let chan = Rc::new(Chan::new());
+    chan.tx_count.set(1);
+    let tx = Tx { chan: chan.clone() };
+    let rx = Rx { chan };
+    (tx, rx)
+}
+
+struct Chan<T, const N: usize> {
+    buffer: UnsafeCell<[MaybeUninit<T>; N]>,
+    /// Index of the first item.
+    head: Cell<usize>,
+    /// Number of items in the buffer.
+    len: Cell<usize>,
+    closed: Cell<bool>,
+    rx_waker: RefCell<Option<Waker>>,
+    /// Senders waiting for a free slot.
+    tx_wakers: RefCell<Vec<Waker>>,
+    tx_count: Cell<usize>,
+}
+
+impl<T, const N: usize> Chan<T, N> {
+    fn new() -> Self {
+        Self {
+            buffer: UnsafeCell::new(unsafe { MaybeUninit::uninit().assume_init() }),
+            head: Cell::new(0),
+            len: Cell::new(0),
+            closed: Cell::new(false),
+            rx_waker: RefCell::new(None),
+            tx_wakers: RefCell::new(Vec::new()),
+            tx_count: Cell::new(0),
+        }
+    }
+
+    /// Push data into the buffer.
+    /// # Safety: Make sure the buffer is not full.
+    unsafe fn push_unchecked(&self, value: T) {
+        let len = self.len.get();
+        debug_assert!(len < N, "buffer is full while push_unchecked");
+        let offset = (self.head.get() + len) % N;
+        (*self.buffer.get())[offset] = MaybeUninit::new(value);
+        self.len.set(len + 1);
+    }
+
+    /// Pop data out.
+    /// # Safety: Make sure there is still some data inside.
+    unsafe fn pop_unchecked(&self) -> T {
+        let len = self.len.get();
+        debug_assert!(len > 0, "buffer is empty while pop_unchecked");
+        let offset = self.head.get();
+        self.head.set((offset + 1) % N);
+        self.len.set(len - 1);
+        let value = std::mem::replace(&mut (*self.buffer.get())[offset], MaybeUninit::uninit());
+        value.assume_init()
+    }
+
+    fn close(&self) {
+        self.closed.set(true);
+        self.wake_senders();
+        self.wake_receiver();
+    }
+
+    fn wake_senders(&self) {
+        let wakers = std::mem::take(&mut *self.tx_wakers.borrow_mut());
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+
+    fn wake_receiver(&self) {
+        if let Some(w) = self.rx_waker.take() {
+            w.wake();
+        }
+    }
+}
+
+impl<T, const N: usize> Drop for Chan<T, N> {
+    fn drop(&mut self) {
+        while self.len.get() > 0 {
+            drop(unsafe { self.pop_unchecked() });
+        }
+    }
+}
+
+impl<T, const N: usize> Tx<T, N> {
+    pub async fn send(&self, value: T) -> Result<(), SendError<T>> {
+        if poll_fn(|cx| self.poll_reserve(cx)).await.is_err() {
+            return Err(SendError::RxClosed(value));
+        }
+        unsafe { self.chan.push_unchecked(value) };
+        self.chan.wake_receiver();
+        Ok(())
+    }
+
+    /// Wait until there is a free slot in the buffer.
+    fn poll_reserve(&self, cx: &mut Context<'_>) -> Poll<Result<(), SendError<()>>> {
+        if self.chan.closed.get() {
+            return Poll::Ready(Err(SendError::RxClosed(())));
+        }
+        if self.chan.len.get() < N {
+            return Poll::Ready(Ok(()));
+        }
+        let mut wakers = self.chan.tx_wakers.borrow_mut();
+        if !wakers.iter().any(|w| w.will_wake(cx.waker())) {
+            wakers.push(cx.waker().clone());
+        }
+        Poll::Pending
+    }
+
+    pub fn close(&self) {
+        self.chan.close()
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.chan.closed.get()
+    }
+
+    pub fn same_channel(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.chan, &other.chan)
+    }
+
+    pub fn hint(&self) -> usize {
+        self.chan.len.get()
+    }
+}
+
+impl<T, const N: usize> Clone for Tx<T, N> {
+    fn clone(&self) -> Self {
+        self.chan.tx_count.set(self.chan.tx_count.get() + 1);
+        Self {
+            chan: self.chan.clone(),
+        }
+    }
+}
+
+impl<T, const N: usize> Drop for Tx<T, N> {
+    fn drop(&mut self) {
+        let cnt = self.chan.tx_count.get();
+        self.chan.tx_count.set(cnt - 1);
+
+        if cnt == 1 {
+            self.chan.close();
+        }
+    }
+}
+
+impl<T, const N: usize> Rx<T, N> {
+    pub async fn recv(&mut self) -> Option<T> {
+        poll_fn(|cx| self.poll_recv(cx)).await
+    }
+
+    pub fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        match self.try_recv() {
+            Ok(val) => return Poll::Ready(Some(val)),
+            Err(TryRecvError::Empty) => {}
+            Err(_) => return Poll::Ready(None),
+        }
+        let mut borrowed = self.chan.rx_waker.borrow_mut();
+        match borrowed.as_mut() {
+            Some(inner) => {
+                if !inner.will_wake(cx.waker()) {
+                    *inner = cx.waker().clone();
+                }
+            }
+            None => {
+                *borrowed = Some(cx.waker().clone());
+            }
+        }
+        Poll::Pending
+    }
+
+    pub fn try_recv(&mut self) -> Result<T, TryRecvError> {
+        if self.chan.len.get() > 0 {
+            let val = unsafe { self.chan.pop_unchecked() };
+            self.chan.wake_senders();
+            return Ok(val);
+        }
+        if self.chan.tx_count.get() == 0 || self.chan.closed.get() {
+            Err(TryRecvError::Disconnected)
+        } else {
+            Err(TryRecvError::Empty)
+        }
+    }
+
+    pub fn close(&self) {
+        self.chan.close()
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.chan.closed.get()
+    }
+
+    pub fn hint(&self) -> usize {
+        self.chan.len.get()
+    }
+}
+
+impl<T, const N: usize> Drop for Rx<T, N> {
+    fn drop(&mut self) {
+        // close the channel, this will make tx send await return.
+        self.chan.close();
+        // consume all elements
+        while self.chan.len.get() > 0 {
+            drop(unsafe { self.chan.pop_unchecked() });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::channel;
+
+    #[monoio::test]
+    async fn test_array_channel() {
+        let (tx, mut rx) = channel::<_, 2>();
+        tx.send(1).await.unwrap();
+        tx.send(2).await.unwrap();
+        assert_eq!(tx.hint(), 2);
+
+        let tx2 = tx.clone();
+        let join = monoio::spawn(async move {
+            tx2.send(3).await.unwrap();
+            tx2.send(4).await.unwrap();
+        });
+        for i in 1..=4 {
+            assert_eq!(rx.recv().await, Some(i));
+        }
+        join.await;
+
+        drop(tx);
+        assert_eq!(rx.recv().await, None);
+    }
+
+    #[monoio::test]
+    async fn test_array_channel_rx_drop() {
+        let (tx, rx) = channel::<_, 1>();
+        tx.send(1).await.unwrap();
+        drop(rx);
+        assert!(tx.is_closed());
+        assert!(tx.send(2).await.is_err());
+    }
+}
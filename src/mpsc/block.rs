@@ -127,6 +127,151 @@ impl<T> Queue<T> {
         value.assume_init()
     }
 
+    /// Push data to the front of the queue, it will be popped next.
+    /// # Safety: Make sure the current capacity is allowed.
+    pub(crate) unsafe fn push_front_unchecked(&mut self, value: T) {
+        if self.head.as_ref().begin == 0 {
+            if self.is_empty() {
+                // head and tail are the same empty block
+                self.push_unchecked(value);
+                return;
+            }
+            // Put a block in front of the head. It is filled from the end, so
+            // every block but the tail still ends at BLOCK_CAP.
+            let tail = self.tail.as_mut();
+            let ptr = match tail.next {
+                Some(mut ptr) => {
+                    tail.next = ptr.as_ref().next;
+                    ptr.as_mut().reset();
+                    ptr
+                }
+                None => NonNull::new_unchecked(Box::into_raw(Box::new(Block::new()))),
+            };
+            let blk = &mut *ptr.as_ptr();
+            blk.begin = BLOCK_CAP;
+            blk.end = BLOCK_CAP;
+            blk.next = Some(self.head);
+            self.head = ptr;
+        }
+        let blk = self.head.as_mut();
+        blk.begin -= 1;
+        (*blk.values.get())[blk.begin] = MaybeUninit::new(value);
+        self.len += 1;
+    }
+
+    /// Get a reference to the first element without popping it.
+    pub(crate) fn peek(&self) -> Option<&T> {
+        if self.is_empty() {
+            return None;
+        }
+        // The head block always holds the first element when the queue is not
+        // empty: it is only advanced once it has been fully consumed.
+        unsafe {
+            let blk = self.head.as_ref();
+            debug_assert!(!blk.is_empty(), "head block is empty while peek");
+            Some((*blk.values.get())[blk.begin].assume_init_ref())
+        }
+    }
+
+    /// Retain only the elements specified by the predicate, keeping order.
+    /// The removed elements are moved to `removed`, so the caller decides
+    /// when they are dropped.
+    ///
+    /// If `f` panics, the elements not checked yet are kept.
+    pub(crate) fn retain<F>(&mut self, removed: &mut Vec<T>, mut f: F)
+    where
+        F: FnMut(&T) -> bool,
+    {
+        // Rotates the elements left on drop, so the queue stays in order
+        // even if `f` panics.
+        struct Rotate<'a, T> {
+            queue: &'a mut Queue<T>,
+            left: usize,
+        }
+
+        impl<T> Drop for Rotate<'_, T> {
+            fn drop(&mut self) {
+                for _ in 0..self.left {
+                    unsafe {
+                        let value = self.queue.pop_unchecked();
+                        self.queue.push_unchecked(value);
+                    }
+                }
+            }
+        }
+
+        // Rotate every element through the queue once: pop from the head and
+        // push the kept ones back to the tail. Popped blocks are recycled to
+        // the tail, so this does not allocate more blocks than it frees.
+        let left = self.len;
+        let mut rotate = Rotate { queue: self, left };
+        while rotate.left > 0 {
+            // checked before it is popped, a panic leaves it in the queue
+            let keep = f(rotate.queue.peek().unwrap());
+            let value = unsafe { rotate.queue.pop_unchecked() };
+            rotate.left -= 1;
+            if keep {
+                unsafe { rotate.queue.push_unchecked(value) };
+            } else {
+                removed.push(value);
+            }
+        }
+    }
+
+    /// Remove and return the first element specified by the predicate, keeping
+    /// the order of the others. The first `skip` elements are not checked.
+    pub(crate) fn remove_first<F>(&mut self, skip: usize, mut f: F) -> Option<T>
+    where
+        F: FnMut(&T) -> bool,
+    {
+        if skip >= self.len {
+            return None;
+        }
+        unsafe {
+            // Find the index of the first match. Only the head block may have
+            // a non-zero begin index, so the position can be computed in one go.
+            let mut blk = self.head;
+            let mut offset = blk.as_ref().begin + skip;
+            while offset >= BLOCK_CAP {
+                blk = blk.as_ref().next.expect("no next block while remove_first");
+                offset -= BLOCK_CAP;
+            }
+            let mut found = None;
+            for idx in skip..self.len {
+                if offset == BLOCK_CAP {
+                    blk = blk.as_ref().next.expect("no next block while remove_first");
+                    offset = 0;
+                }
+                if f((*blk.as_ref().values.get())[offset].assume_init_ref()) {
+                    found = Some(idx);
+                    break;
+                }
+                offset += 1;
+            }
+            let found = found?;
+
+            // Shift the elements before the match one slot towards the tail, put the match
+            // at the head and pop it.
+            let mut blk = self.head;
+            let mut offset = blk.as_ref().begin;
+            let mut carry = std::mem::replace(
+                &mut (*blk.as_ref().values.get())[offset],
+                MaybeUninit::uninit(),
+            );
+            for _ in 0..found {
+                offset += 1;
+                if offset == BLOCK_CAP {
+                    blk = blk.as_ref().next.expect("no next block while remove_first");
+                    offset = 0;
+                }
+                carry = std::mem::replace(&mut (*blk.as_ref().values.get())[offset], carry);
+            }
+            let head = self.head.as_ref();
+            (*head.values.get())[head.begin] = carry;
+            Some(self.pop_unchecked())
+        }
+    }
+
     /// Free all blocks.
     /// # Safety: Free blocks and drop. Must make sure you drop all elements first.
     pub(crate) unsafe fn free_blocks(&mut self) {
@@ -166,6 +311,86 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_peek_retain() {
+        let mut queue = Queue::new();
+        assert!(queue.peek().is_none());
+        unsafe {
+            for idx in 0..100 {
+                queue.push_unchecked(idx);
+            }
+            assert_eq!(queue.peek(), Some(&0));
+            let mut removed = Vec::new();
+            queue.retain(&mut removed, |v| v % 3 == 0);
+            assert_eq!(removed.len(), 66);
+            assert_eq!(removed[..3], [1, 2, 4]);
+            assert_eq!(queue.len(), 34);
+            for idx in 0..34 {
+                assert_eq!(queue.peek(), Some(&(idx * 3)));
+                assert_eq!(queue.pop_unchecked(), idx * 3);
+            }
+            assert!(queue.is_empty());
+            queue.free_blocks();
+        }
+    }
+
+    #[test]
+    fn test_remove_first() {
+        let mut queue = Queue::new();
+        unsafe {
+            // make the head block start at a non-zero index
+            for idx in 0..10 {
+                queue.push_unchecked(idx);
+            }
+            for idx in 0..5 {
+                assert_eq!(queue.pop_unchecked(), idx);
+            }
+            for idx in 10..100 {
+                queue.push_unchecked(idx);
+            }
+            assert_eq!(queue.remove_first(0, |v| *v == 60), Some(60));
+            assert_eq!(queue.remove_first(0, |v| *v == 60), None);
+            assert_eq!(queue.remove_first(10, |v| *v < 10), None);
+            assert_eq!(queue.remove_first(4, |v| *v < 10), Some(9));
+            assert_eq!(queue.len(), 93);
+            for idx in (5..9).chain(10..60).chain(61..100) {
+                assert_eq!(queue.pop_unchecked(), idx);
+            }
+            assert!(queue.is_empty());
+            queue.free_blocks();
+        }
+    }
+
+    #[test]
+    fn test_push_front() {
+        let mut queue = Queue::new();
+        unsafe {
+            queue.push_front_unchecked(1);
+            queue.push_unchecked(2);
+            // front block is full from the start
+            for idx in 0..40 {
+                queue.push_front_unchecked(-idx);
+            }
+            assert_eq!(queue.len(), 42);
+            assert_eq!(queue.remove_first(39, |v| *v == 1), Some(1));
+            for idx in (0..40).rev() {
+                assert_eq!(queue.pop_unchecked(), -idx);
+            }
+            assert_eq!(queue.pop_unchecked(), 2);
+            assert!(queue.is_empty());
+            // blocks freed by popping are reused
+            for idx in 0..100 {
+                queue.push_unchecked(idx);
+                queue.push_front_unchecked(idx);
+                assert_eq!(queue.pop_unchecked(), idx);
+            }
+            for idx in 0..100 {
+                assert_eq!(queue.pop_unchecked(), idx);
+            }
+            queue.free_blocks();
+        }
+    }
+
     #[test]
     fn test_across_block_push_pop() {
         let mut queue = Queue::new();
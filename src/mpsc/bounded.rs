@@ -1,38 +1,143 @@
-use super::chan::{self, SendError, TryRecvError};
-use crate::semaphore::Inner;
+use super::{
+    chan::{self, PanicPolicy, RecvError, SendError, TryRecvError},
+    semaphore::{Bounded, Semaphore as _},
+    tee,
+};
+use crate::semaphore::{Inner, Semaphore};
 use futures_lite::future::poll_fn;
-use std::task::{Context, Poll};
+use std::{
+    cell::Ref,
+    future::Future,
+    pin::pin,
+    rc::Rc,
+    task::{Context, Poll},
+};
 
-pub struct Tx<T>(chan::Tx<T, Inner>);
+pub struct Tx<T, E = ()>(chan::Tx<T, Bounded, E>);
 
-pub struct Rx<T>(chan::Rx<T, Inner>);
+pub struct Rx<T, E = ()>(chan::Rx<T, Bounded, E>);
+
+/// Receiver which yields values in batches, see [`Rx::batched`].
+pub type Batched<T, E = ()> = chan::Batched<Rx<T, E>>;
 
 pub fn channel<T>(buffer: usize) -> (Tx<T>, Rx<T>) {
-    let semaphore = Inner::new(buffer);
+    let semaphore = Bounded::Owned(Inner::new(buffer));
     let (tx, rx) = chan::channel(semaphore);
     (Tx(tx), Rx(rx))
 }
 
-impl<T> Tx<T> {
-    pub async fn send(&self, value: T) -> Result<(), SendError> {
+/// Creates a channel whose capacity is a budget of weight rather than a
+/// number of values, e.g. a number of bytes.
+///
+/// Sending a value waits until `weigh(&value)` of the budget is free, and
+/// receiving it gives the same amount back, so `weigh` must return the same
+/// weight for a value every time. A value heavier than the whole budget
+/// counts as `budget`: it is sent once the channel is empty. A value of
+/// weight 0 counts as 1.
+///
+/// # Panics
+///
+/// Panics if `budget` is 0.
+pub fn channel_weighted<T>(budget: usize, weigh: fn(&T) -> u32) -> (Tx<T>, Rx<T>) {
+    assert!(budget > 0, "weight budget must be greater than 0");
+    let semaphore = Bounded::Owned(Inner::new(budget));
+    let max = budget.min(u32::MAX as usize) as u32;
+    let (tx, rx) = chan::channel_weighted(semaphore, weigh, max);
+    (Tx(tx), Rx(rx))
+}
+
+/// Creates a channel which takes its capacity from `semaphore`, which can be
+/// shared by many channels and other users to put a single limit on all of
+/// them.
+///
+/// Sending a value acquires a permit from the semaphore, and the permit is
+/// given back when the value is received or dropped with the receiver.
+/// Closing the channel does not close the semaphore.
+pub fn channel_with_semaphore<T>(semaphore: Rc<Semaphore>) -> (Tx<T>, Rx<T>) {
+    let (tx, rx) = chan::channel(Bounded::shared(semaphore));
+    (Tx(tx), Rx(rx))
+}
+
+/// Creates a channel whose senders can close it with a reason of type `E`,
+/// see [`Tx::close_with`].
+pub fn channel_with_reason<T, E>(buffer: usize) -> (Tx<T, E>, Rx<T, E>) {
+    let semaphore = Bounded::Owned(Inner::new(buffer));
+    let (tx, rx) = chan::channel_with_reason(semaphore);
+    (Tx(tx), Rx(rx))
+}
+
+impl<T, E> Tx<T, E> {
+    pub async fn send(&self, value: T) -> Result<(), SendError<T>> {
         // acquire semaphore first
-        self.0
-            .chan
-            .semaphore
-            .acquire(1)
+        let weight = self.0.weight(&value);
+        let semaphore = &self.0.chan.semaphore;
+        let acquired = if semaphore.is_shared() {
+            // a shared semaphore is not closed with the channel, so we have to
+            // wait for the channel to be closed as well.
+            let mut acquire = pin!(semaphore.acquire(weight));
+            let mut closed = pin!(self.0.closed());
+            poll_fn(|cx| {
+                if let Poll::Ready(res) = acquire.as_mut().poll(cx) {
+                    return Poll::Ready(res.is_ok());
+                }
+                closed.as_mut().poll(cx).map(|_| false)
+            })
             .await
-            .map_err(|_| SendError::RxClosed)?;
-        self.0.send(value)
+        } else {
+            semaphore.acquire(weight).await.is_ok()
+        };
+        if !acquired {
+            return Err(SendError::RxClosed(value));
+        }
+        self.0.send_weighted(value, weight).inspect_err(|_| {
+            // closed after the permit was acquired
+            semaphore.add_permits(weight as usize);
+        })
     }
 
+    /// Closes the channel: later sends fail and give the value back, and the
+    /// receiver ends once it has received all queued values.
     pub fn close(&self) {
         self.0.close()
     }
 
+    /// Closes the channel for all senders, e.g. because the producer failed.
+    ///
+    /// Values sent before are still received, after which the receiver sees
+    /// [`RecvError::Closed`] with this reason from [`Rx::recv_result`], or
+    /// [`TryRecvError::Closed`] from [`Rx::try_recv`]. The reason is moved out
+    /// to the first of them, and only the first reason is kept.
+    pub fn close_with(&self, reason: E) {
+        self.0.close_with(reason)
+    }
+
     pub fn is_closed(&self) -> bool {
         self.0.is_closed()
     }
 
+    /// Completes when the receiver has been dropped or the channel has been
+    /// closed.
+    ///
+    /// This is useful to stop producing values nobody will receive.
+    pub async fn closed(&self) {
+        self.0.closed().await
+    }
+
+    /// Checks whether the channel has been closed, and if not, schedules the
+    /// `Waker` in the provided `Context` to be notified when it is.
+    ///
+    /// Only the `Waker` from the most recent call is notified.
+    pub fn poll_closed(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        self.0.poll_closed(cx)
+    }
+
+    /// Ends the batch a [`Batched`] receiver is collecting, even if it is not
+    /// full. If nothing is collected yet, the next batch ends with its first
+    /// value.
+    pub fn flush(&self) {
+        self.0.flush()
+    }
+
     pub fn same_channel(&self, other: &Self) -> bool {
         self.0.same_channel(&other.0)
     }
@@ -42,13 +147,20 @@ impl<T> Tx<T> {
     }
 }
 
-impl<T> Clone for Tx<T> {
+impl<T, E> Clone for Tx<T, E> {
     fn clone(&self) -> Self {
         Self(self.0.clone())
     }
 }
 
-impl<T> Rx<T> {
+impl<T, E> Rx<T, E> {
+    /// Receives the next value, or `None` once the channel is closed and
+    /// empty.
+    ///
+    /// This does not tell why the channel ended, [`Rx::recv_result`] and
+    /// [`Rx::try_recv`] return the reason given to [`Tx::close_with`]. `recv`
+    /// keeps returning an `Option` so that receiving works the same for every
+    /// channel, e.g. in a [`RxSet`](super::rx_set::RxSet).
     pub async fn recv(&mut self) -> Option<T> {
         poll_fn(|cx| self.poll_recv(cx)).await
     }
@@ -57,10 +169,68 @@ impl<T> Rx<T> {
         self.0.recv(cx)
     }
 
-    pub fn try_recv(&mut self) -> Result<T, TryRecvError> {
+    /// Like `recv`, but tells apart why the channel ended.
+    pub async fn recv_result(&mut self) -> Result<T, RecvError<E>> {
+        poll_fn(|cx| self.poll_recv_result(cx)).await
+    }
+
+    pub fn poll_recv_result(&mut self, cx: &mut Context<'_>) -> Poll<Result<T, RecvError<E>>> {
+        self.0.recv_result(cx)
+    }
+
+    /// Receives a value if one is queued. Once the channel has ended, tells
+    /// apart why like [`Rx::recv_result`].
+    pub fn try_recv(&mut self) -> Result<T, TryRecvError<E>> {
         self.0.try_recv()
     }
 
+    /// Receives the first value for which `f` returns `true`, leaving the
+    /// other values queued in order.
+    ///
+    /// Returns `None` if the channel is closed and no queued value matches.
+    pub async fn recv_matching<F>(&mut self, mut f: F) -> Option<T>
+    where
+        F: FnMut(&T) -> bool,
+    {
+        let mut scanned = 0;
+        poll_fn(|cx| self.0.recv_matching(cx, &mut scanned, &mut f)).await
+    }
+
+    pub fn try_recv_matching<F>(&mut self, f: F) -> Result<T, TryRecvError>
+    where
+        F: FnMut(&T) -> bool,
+    {
+        self.0.try_recv_matching(f)
+    }
+
+    /// Returns a reference to the next value without receiving it.
+    ///
+    /// The channel can not be sent to while the returned `Ref` is alive,
+    /// sending in the meantime will panic.
+    pub fn peek(&self) -> Option<Ref<'_, T>> {
+        self.0.peek()
+    }
+
+    /// Receives all values currently queued without waiting. Each value
+    /// frees its slot as soon as it is yielded.
+    pub fn drain(&mut self) -> impl Iterator<Item = T> + '_ {
+        self.0.drain()
+    }
+
+    /// Retains only the queued values for which `f` returns `true`, and
+    /// frees the slots of the removed ones. Order is preserved.
+    pub fn retain<F>(&mut self, f: F)
+    where
+        F: FnMut(&T) -> bool,
+    {
+        self.0.retain(f)
+    }
+
+    /// Closes the channel without dropping the receiver.
+    ///
+    /// Later sends fail and give the value back, while values already queued
+    /// can still be received. Once the queue is empty, `recv` returns `None`
+    /// and `try_recv` returns `Disconnected`, even if senders are still alive.
     pub fn close(&self) {
         self.0.close()
     }
@@ -69,14 +239,91 @@ impl<T> Rx<T> {
         self.0.is_closed()
     }
 
+    /// Turns the receiver into one which yields values in batches of up to
+    /// `max_items` values.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max_items` is 0.
+    pub fn batched(self, max_items: usize) -> Batched<T, E> {
+        Batched::new(self, max_items)
+    }
+
+    /// Splits the receiver into `n` receivers which each get a clone of every
+    /// value, see [`tee`](super::tee::tee).
+    pub fn tee(self, n: usize, capacity: usize, policy: tee::Policy) -> Vec<tee::Rx<T>>
+    where
+        T: Clone + 'static,
+        E: 'static,
+    {
+        tee::tee(self, n, capacity, policy)
+    }
+
+    /// Sets which panicking senders poison the channel, the default is
+    /// [`PanicPolicy::LastSender`].
+    pub fn set_panic_policy(&self, policy: PanicPolicy) {
+        self.0.set_panic_policy(policy)
+    }
+
+    /// Returns `true` if a sender has been dropped while panicking. Once the
+    /// queue is drained, `recv_result` and `try_recv` return `SenderPanicked`.
+    pub fn is_poisoned(&self) -> bool {
+        self.0.is_poisoned()
+    }
+
     pub fn hint(&self) -> usize {
         self.0.hint()
     }
+
+    /// Returns the total weight of the queued values. For a channel not
+    /// created with [`channel_weighted`] this is the number of values.
+    pub fn queued_weight(&self) -> usize {
+        self.0.queued_weight()
+    }
+}
+
+impl<T, E> Batched<T, E> {
+    /// Waits for a batch and appends it to `buf`, so the same buffer can be
+    /// reused for every batch. Returns the number of values appended.
+    ///
+    /// A batch ends once it is full, a sender calls `flush`, or the channel is
+    /// closed. A `flush` while no value is collected yet ends the batch as
+    /// soon as it has one. Returns 0 once the channel is closed and all
+    /// values have been received.
+    ///
+    /// If the future is dropped, the values already moved to `buf` stay
+    /// there and still count towards the batch.
+    pub async fn recv_batch(&mut self, buf: &mut Vec<T>) -> usize {
+        poll_fn(|cx| self.poll_recv_batch(cx, buf)).await
+    }
+
+    pub fn poll_recv_batch(&mut self, cx: &mut Context<'_>, buf: &mut Vec<T>) -> Poll<usize> {
+        let (rx, batch) = self.parts_mut();
+        rx.0.recv_batch(cx, buf, batch)
+    }
+
+    /// Also ends a batch once the weight of its values reaches `max_weight`,
+    /// see [`channel_weighted`]. The last value may take the batch over it.
+    pub fn max_weight(mut self, max_weight: usize) -> Self {
+        self.set_max_weight(max_weight);
+        self
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::channel;
+    use super::{channel, channel_weighted, channel_with_reason, channel_with_semaphore, Tx};
+    use crate::{
+        mpsc::{RecvError, SendError, TryRecvError},
+        semaphore::Semaphore,
+    };
+    use futures_lite::future::{poll_fn, poll_once};
+    use std::{
+        cell::{Cell, RefCell},
+        panic::{catch_unwind, AssertUnwindSafe},
+        rc::Rc,
+        task::Poll,
+    };
 
     #[monoio::test]
     async fn tets_bounded_channel() {
@@ -87,4 +334,263 @@ mod tests {
         drop(tx);
         assert_eq!(rx.recv().await, None);
     }
+
+    #[monoio::test]
+    async fn test_bounded_channel_rx_close() {
+        let (tx, mut rx) = channel(1);
+        tx.send(1).await.unwrap();
+
+        let tx2 = tx.clone();
+        let join = monoio::spawn(async move { tx2.send(2).await });
+        rx.close();
+        // blocked senders get their value back
+        assert_eq!(join.await.unwrap_err().into_inner(), 2);
+        assert_eq!(tx.send(3).await, Err(SendError::RxClosed(3)));
+
+        // queued values can still be received, then the channel ends even
+        // though a sender is alive
+        assert_eq!(rx.recv().await, Some(1));
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Disconnected));
+        assert_eq!(rx.recv().await, None);
+    }
+
+    #[monoio::test]
+    async fn test_bounded_channel_close_with() {
+        let (tx, mut rx) = channel_with_reason::<_, &str>(1);
+        tx.send(1).await.unwrap();
+
+        // blocked senders are released with an error
+        let tx2 = tx.clone();
+        let join = monoio::spawn(async move { tx2.send(2).await });
+        tx.close_with("connection reset");
+        assert!(join.await.is_err());
+
+        assert_eq!(rx.recv_result().await, Ok(1));
+        assert_eq!(
+            rx.recv_result().await,
+            Err(RecvError::Closed("connection reset"))
+        );
+        // the reason is only returned once
+        assert_eq!(rx.recv_result().await, Err(RecvError::Disconnected));
+        assert_eq!(rx.recv().await, None);
+
+        let (tx, mut rx) = channel_with_reason::<u32, _>(1);
+        tx.close_with("connection reset");
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Closed("connection reset")));
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Disconnected));
+    }
+
+    #[monoio::test]
+    async fn test_bounded_channel_inspect() {
+        let (tx, mut rx) = channel(4);
+        for i in 0..4 {
+            tx.send(i).await.unwrap();
+        }
+        assert_eq!(*rx.peek().unwrap(), 0);
+
+        // removing values frees capacity for blocked senders
+        let tx2 = tx.clone();
+        let join = monoio::spawn(async move {
+            tx2.send(4).await.unwrap();
+            tx2.send(5).await.unwrap();
+        });
+        rx.retain(|v| v % 2 == 1);
+        join.await;
+        assert_eq!(rx.hint(), 4);
+
+        assert_eq!(rx.drain().collect::<Vec<_>>(), vec![1, 3, 4, 5]);
+        assert!(rx.peek().is_none());
+        // drained values have freed their capacity
+        for i in 0..4 {
+            tx.send(i).await.unwrap();
+        }
+    }
+
+    /// Records the length of the queue when it is dropped.
+    struct Probe {
+        id: u32,
+        tx: Rc<RefCell<Option<Tx<Probe>>>>,
+        hint: Rc<Cell<usize>>,
+    }
+
+    impl Drop for Probe {
+        fn drop(&mut self) {
+            if let Some(tx) = self.tx.borrow().as_ref() {
+                self.hint.set(tx.hint());
+            }
+        }
+    }
+
+    #[monoio::test]
+    async fn test_bounded_channel_retain_drop() {
+        let (tx, mut rx) = channel(4);
+        let shared = Rc::new(RefCell::new(None));
+        let hint = Rc::new(Cell::new(0));
+        let probe = |id| Probe {
+            id,
+            tx: shared.clone(),
+            hint: hint.clone(),
+        };
+        for id in 0..4 {
+            tx.send(probe(id)).await.unwrap();
+        }
+        *shared.borrow_mut() = Some(tx.clone());
+
+        // removed values are dropped once the queue is no longer borrowed
+        rx.retain(|p| p.id % 2 == 0);
+        assert_eq!(hint.get(), 2);
+
+        // a panicking predicate keeps the values not checked yet, in order,
+        // and the values removed before still free their capacity
+        tx.send(probe(4)).await.unwrap();
+        tx.send(probe(5)).await.unwrap();
+        let res = catch_unwind(AssertUnwindSafe(|| {
+            rx.retain(|p| match p.id {
+                0 => false,
+                2 => panic!("predicate panicked"),
+                _ => true,
+            })
+        }));
+        assert!(res.is_err());
+        assert_eq!(hint.get(), 3);
+        assert!(poll_once(tx.send(probe(6))).await.is_some());
+
+        shared.borrow_mut().take();
+        let ids = rx.drain().map(|p| p.id).collect::<Vec<_>>();
+        assert_eq!(ids, vec![2, 4, 5, 6]);
+    }
+
+    #[monoio::test]
+    async fn test_bounded_channel_poll_closed() {
+        let (mut tx, rx) = channel::<u32>(1);
+        assert!(poll_fn(|cx| Poll::Ready(tx.poll_closed(cx)))
+            .await
+            .is_pending());
+        // a registered sender unlinks itself on drop
+        let mut tx2 = tx.clone();
+        assert!(poll_fn(|cx| Poll::Ready(tx2.poll_closed(cx)))
+            .await
+            .is_pending());
+        drop(tx2);
+        rx.close();
+        assert!(poll_fn(|cx| Poll::Ready(tx.poll_closed(cx)))
+            .await
+            .is_ready());
+        tx.closed().await;
+    }
+
+    #[monoio::test]
+    async fn test_bounded_channel_weighted() {
+        let (tx, mut rx) = channel_weighted::<Vec<u8>>(10, |v| v.len() as u32);
+        tx.send(vec![0; 4]).await.unwrap();
+        tx.send(vec![1; 6]).await.unwrap();
+        assert_eq!(rx.queued_weight(), 10);
+
+        // waits until enough of the budget is free
+        let tx2 = tx.clone();
+        let join = monoio::spawn(async move {
+            tx2.send(vec![2; 5]).await.unwrap();
+            // heavier than the budget, waits for an empty channel
+            tx2.send(vec![3; 20]).await.unwrap();
+        });
+        assert_eq!(rx.recv().await.unwrap().len(), 4);
+        assert_eq!(rx.recv().await.unwrap().len(), 6);
+        assert_eq!(rx.recv().await.unwrap().len(), 5);
+        assert_eq!(rx.recv().await.unwrap().len(), 20);
+        join.await;
+        assert_eq!(rx.queued_weight(), 0);
+
+        tx.send(vec![4; 3]).await.unwrap();
+        tx.send(vec![5; 3]).await.unwrap();
+        rx.retain(|v| v[0] == 5);
+        assert_eq!(rx.queued_weight(), 3);
+
+        // empty values still take a permit
+        tx.send(vec![]).await.unwrap();
+        assert_eq!(rx.queued_weight(), 4);
+        assert_eq!(rx.recv().await.unwrap().len(), 3);
+        assert!(rx.recv().await.unwrap().is_empty());
+        assert_eq!(rx.queued_weight(), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "weight budget must be greater than 0")]
+    fn test_bounded_channel_weighted_zero_budget() {
+        let _ = channel_weighted::<Vec<u8>>(0, |v| v.len() as u32);
+    }
+
+    #[monoio::test]
+    async fn test_bounded_channel_weight_changes_while_queued() {
+        let (tx, mut rx) = channel_weighted::<Rc<Cell<u32>>>(10, |v| v.get());
+        let value = Rc::new(Cell::new(4));
+        tx.send(value.clone()).await.unwrap();
+        // the permits taken on send are given back, not the new weight
+        value.set(9);
+        rx.recv().await.unwrap();
+        assert_eq!(rx.queued_weight(), 0);
+        assert!(poll_once(tx.send(Rc::new(Cell::new(10)))).await.is_some());
+        assert_eq!(rx.queued_weight(), 10);
+    }
+
+    #[monoio::test]
+    async fn test_bounded_channel_shared_semaphore() {
+        let semaphore = Rc::new(Semaphore::new(2));
+        let (tx1, mut rx1) = channel_with_semaphore(semaphore.clone());
+        let (tx2, rx2) = channel_with_semaphore(semaphore.clone());
+        tx1.send(1).await.unwrap();
+        tx2.send(2).await.unwrap();
+        assert_eq!(semaphore.available_permits(), 0);
+
+        // both channels are full now
+        let join = monoio::spawn(async move {
+            tx1.send(3).await.unwrap();
+        });
+        assert_eq!(rx1.recv().await, Some(1));
+        join.await;
+        assert_eq!(rx1.recv().await, Some(3));
+        assert_eq!(semaphore.available_permits(), 1);
+
+        // dropping a receiver gives back its permits, but leaves the
+        // semaphore open for the other channels
+        let tx3 = tx2.clone();
+        drop(rx2);
+        assert_eq!(semaphore.available_permits(), 2);
+        assert!(!semaphore.is_closed());
+        assert_eq!(tx3.send(4).await, Err(SendError::RxClosed(4)));
+        assert_eq!(semaphore.available_permits(), 2);
+    }
+
+    #[monoio::test]
+    async fn test_bounded_channel_shared_semaphore_close() {
+        let semaphore = Rc::new(Semaphore::new(1));
+        let _permit = semaphore.try_acquire().unwrap();
+        let (tx, rx) = channel_with_semaphore(semaphore.clone());
+        // a sender waiting for the shared semaphore sees the channel close
+        let join = monoio::spawn(async move { tx.send(1).await });
+        futures_lite::future::yield_now().await;
+        drop(rx);
+        assert_eq!(join.await, Err(SendError::RxClosed(1)));
+    }
+
+    #[monoio::test]
+    async fn test_bounded_channel_batched() {
+        let (tx, rx) = channel_weighted::<Vec<u8>>(100, |v| v.len() as u32);
+        let mut rx = rx.batched(10).max_weight(8);
+        let mut buf = Vec::with_capacity(10);
+        for len in [3, 3, 3, 1, 1] {
+            tx.send(vec![0; len]).await.unwrap();
+        }
+        // the batch ends with the value reaching the weight
+        assert_eq!(rx.recv_batch(&mut buf).await, 3);
+        buf.clear();
+
+        let join = monoio::spawn(async move {
+            tx.send(vec![0; 2]).await.unwrap();
+        });
+        assert_eq!(rx.recv_batch(&mut buf).await, 3);
+        join.await;
+        assert_eq!(rx.recv_batch(&mut buf).await, 0);
+        assert_eq!(buf.iter().map(Vec::len).collect::<Vec<_>>(), [1, 1, 2]);
+        assert_eq!(rx.into_inner().queued_weight(), 0);
+    }
 }
@@ -1,14 +1,23 @@
 use std::{
-    cell::{Cell, RefCell},
+    cell::{Cell, Ref, RefCell},
     error::Error,
     fmt,
+    pin::Pin,
     rc::Rc,
     task::{Context, Poll, Waker},
 };
 
 use super::{block::Queue, semaphore::Semaphore};
+use crate::notify::{Notify, Waiter};
 
 pub(crate) fn channel<T, S>(semaphore: S) -> (Tx<T, S>, Rx<T, S>)
+where
+    S: Semaphore,
+{
+    channel_with_reason(semaphore)
+}
+
+pub(crate) fn channel_with_reason<T, S, E>(semaphore: S) -> (Tx<T, S, E>, Rx<T, S, E>)
 where
     S: Semaphore,
 {
@@ -18,36 +27,195 @@ where
     (tx, rx)
 }
 
-pub(crate) struct Chan<T, S: Semaphore> {
-    queue: RefCell<Queue<T>>,
+/// Creates a channel where each value takes `weigh(value)` permits, at most
+/// `max`.
+pub(crate) fn channel_weighted<T, S>(
+    semaphore: S,
+    weigh: fn(&T) -> u32,
+    max: u32,
+) -> (Tx<T, S>, Rx<T, S>)
+where
+    S: Semaphore,
+{
+    let mut chan = Chan::new(semaphore);
+    chan.weigher = Some(Weigher { weigh, max });
+    let chan = Rc::new(chan);
+    let tx = Tx::new(chan.clone());
+    let rx = Rx::new(chan);
+    (tx, rx)
+}
+
+/// Creates a channel which tells senders when the queue gets long, see
+/// `unbounded::channel_with_watermarks`.
+pub(crate) fn channel_with_watermarks<T, S>(
+    semaphore: S,
+    low: usize,
+    high: usize,
+) -> (Tx<T, S>, Rx<T, S>)
+where
+    S: Semaphore,
+{
+    let mut chan = Chan::new(semaphore);
+    chan.watermarks = Some((low, high));
+    let chan = Rc::new(chan);
+    let tx = Tx::new(chan.clone());
+    let rx = Rx::new(chan);
+    (tx, rx)
+}
+
+struct Weigher<T> {
+    weigh: fn(&T) -> u32,
+    max: u32,
+}
+
+pub(crate) struct Chan<T, S: Semaphore, E = ()> {
+    /// Queued values with the number of permits each of them took.
+    queue: RefCell<Queue<(T, u32)>>,
     pub(crate) semaphore: S,
     rx_waker: RefCell<Option<Waker>>,
+    /// Senders waiting for the channel to be closed, or to cross one of the
+    /// watermarks.
+    tx_waiters: Notify,
     tx_count: Cell<usize>,
+    /// Set when a sender closed the channel with `close_with`.
+    aborted: Cell<bool>,
+    /// The reason given to `close_with`, until the receiver takes it.
+    reason: RefCell<Option<E>>,
+    /// Set when a sender has been dropped while panicking, see `PanicPolicy`.
+    poisoned: Cell<bool>,
+    panic_policy: Cell<PanicPolicy>,
+    /// Without a weigher every value takes one permit.
+    weigher: Option<Weigher<T>>,
+    /// Sum of the weights of the queued values.
+    queued_weight: Cell<usize>,
+    /// Low and high watermarks of `queued_weight`.
+    watermarks: Option<(usize, usize)>,
+    /// Set when `queued_weight` goes over the high watermark, cleared once it
+    /// falls to the low watermark.
+    over_high: Cell<bool>,
+    /// Set by a sender to end the batch the receiver is collecting.
+    flush: Cell<bool>,
+}
+
+/// Limits and progress of the batch collected by `Rx::recv_batch`.
+pub(crate) struct Batch {
+    max_items: usize,
+    max_weight: usize,
+    items: usize,
+    weight: usize,
+}
+
+impl Batch {
+    fn new(max_items: usize) -> Self {
+        Self {
+            max_items,
+            max_weight: usize::MAX,
+            items: 0,
+            weight: 0,
+        }
+    }
+
+    fn is_full(&self) -> bool {
+        self.items >= self.max_items || self.weight >= self.max_weight
+    }
+}
+
+/// Values removed by `Rx::retain`. Releases their permits and drops them on
+/// drop.
+struct Removed<'a, T, S: Semaphore, E> {
+    chan: &'a Chan<T, S, E>,
+    values: Vec<(T, u32)>,
+}
+
+impl<T, S: Semaphore, E> Drop for Removed<'_, T, S, E> {
+    fn drop(&mut self) {
+        let weight = self.values.iter().map(|(_, weight)| *weight as usize).sum();
+        self.chan.release(weight);
+    }
 }
 
 /// Error returned by `try_recv`.
+///
+/// Only the bounded and unbounded channels return `Closed` and
+/// `SenderPanicked`, the other channels end with `Disconnected`.
 #[derive(PartialEq, Eq, Clone, Copy, Debug)]
-pub enum TryRecvError {
+pub enum TryRecvError<E = ()> {
     /// This **channel** is currently empty, but the **Sender**(s) have not yet
     /// disconnected, so data may yet become available.
     Empty,
     /// The **channel**'s sending half has become disconnected, and there will
     /// never be any more data received on it.
     Disconnected,
+    /// See [`RecvError::Closed`].
+    Closed(E),
+    /// See [`RecvError::SenderPanicked`].
+    SenderPanicked,
+}
+
+impl<E> From<RecvError<E>> for TryRecvError<E> {
+    fn from(err: RecvError<E>) -> Self {
+        match err {
+            RecvError::Disconnected => TryRecvError::Disconnected,
+            RecvError::Closed(reason) => TryRecvError::Closed(reason),
+            RecvError::SenderPanicked => TryRecvError::SenderPanicked,
+        }
+    }
 }
 
-impl fmt::Display for TryRecvError {
+impl<E> fmt::Display for TryRecvError<E> {
     fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
         match *self {
             TryRecvError::Empty => "receiving on an empty channel".fmt(fmt),
             TryRecvError::Disconnected => "receiving on a closed channel".fmt(fmt),
+            TryRecvError::Closed(_) => "receiving on a channel closed with a reason".fmt(fmt),
+            TryRecvError::SenderPanicked => "receiving on a channel whose sender panicked".fmt(fmt),
+        }
+    }
+}
+
+impl<E: fmt::Debug> Error for TryRecvError<E> {}
+
+/// Error returned by `recv_result`.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum RecvError<E> {
+    /// All **Sender**s have been dropped without giving a reason.
+    Disconnected,
+    /// A **Sender** closed the channel with the given reason. The reason is
+    /// moved out to the first `recv_result` or `try_recv` which sees the
+    /// channel end, later calls return `Disconnected`.
+    Closed(E),
+    /// A **Sender** has been dropped while its task was panicking, see
+    /// [`PanicPolicy`].
+    SenderPanicked,
+}
+
+impl<E> fmt::Display for RecvError<E> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            RecvError::Disconnected => "receiving on a closed channel".fmt(fmt),
+            RecvError::Closed(_) => "receiving on a channel closed with a reason".fmt(fmt),
+            RecvError::SenderPanicked => "receiving on a channel whose sender panicked".fmt(fmt),
         }
     }
 }
 
-impl Error for TryRecvError {}
+impl<E: fmt::Debug> Error for RecvError<E> {}
 
-impl<T, S> Chan<T, S>
+/// Decides which panicking **Sender**s poison the channel.
+///
+/// A poisoned channel still delivers the values queued before, after which
+/// the receiver gets [`RecvError::SenderPanicked`].
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Default)]
+pub enum PanicPolicy {
+    /// Poison the channel if the last **Sender** is dropped while panicking.
+    #[default]
+    LastSender,
+    /// Poison and close the channel as soon as any **Sender** is dropped while
+    /// panicking, even if other senders are still alive.
+    AnySender,
+}
+
+impl<T, S, E> Chan<T, S, E>
 where
     S: Semaphore,
 {
@@ -57,12 +225,57 @@ where
             queue,
             semaphore,
             rx_waker: RefCell::new(None),
+            tx_waiters: Notify::new(),
             tx_count: Cell::new(0),
+            aborted: Cell::new(false),
+            reason: RefCell::new(None),
+            poisoned: Cell::new(false),
+            panic_policy: Cell::new(PanicPolicy::LastSender),
+            weigher: None,
+            queued_weight: Cell::new(0),
+            watermarks: None,
+            over_high: Cell::new(false),
+            flush: Cell::new(false),
         }
     }
 }
 
-impl<T, S> Drop for Chan<T, S>
+impl<T, S, E> Chan<T, S, E>
+where
+    S: Semaphore,
+{
+    fn close(&self) {
+        self.semaphore.close();
+        self.tx_waiters.notify_waiters();
+    }
+
+    fn weight(&self, value: &T) -> u32 {
+        match &self.weigher {
+            Some(weigher) => (weigher.weigh)(value).clamp(1, weigher.max),
+            None => 1,
+        }
+    }
+
+    /// Give back the permits of received values.
+    fn release(&self, weight: usize) {
+        let queued = self.queued_weight.get() - weight;
+        self.queued_weight.set(queued);
+        self.semaphore.add_permits(weight);
+        if let Some((low, _)) = self.watermarks {
+            if queued <= low && self.over_high.replace(false) {
+                self.tx_waiters.notify_waiters();
+            }
+        }
+    }
+
+    /// Returns `true` if no more values will be sent: either all senders are
+    /// gone, or the channel has been closed by one of the handles.
+    fn is_disconnected(&self) -> bool {
+        self.tx_count.get() == 0 || self.semaphore.is_closed()
+    }
+}
+
+impl<T, S, E> Drop for Chan<T, S, E>
 where
     S: Semaphore,
 {
@@ -79,44 +292,92 @@ where
     }
 }
 
-pub(crate) struct Tx<T, S>
+pub(crate) struct Tx<T, S, E = ()>
 where
     S: Semaphore,
 {
-    pub(crate) chan: Rc<Chan<T, S>>,
+    pub(crate) chan: Rc<Chan<T, S, E>>,
+    /// Registered in `tx_waiters` by `poll_closed`.
+    closed_waiter: Option<Pin<Box<Waiter>>>,
 }
 
-#[derive(PartialEq, Eq, Clone, Copy, Debug)]
-pub enum SendError {
-    RxClosed,
+/// Error returned by `send`, giving back the value which was not sent.
+#[derive(PartialEq, Eq, Clone, Copy)]
+pub enum SendError<T> {
+    /// The channel has been closed, either by the receiver or by a sender.
+    RxClosed(T),
 }
 
-pub(crate) struct Rx<T, S>
+impl<T> SendError<T> {
+    /// Returns the value which was not sent.
+    pub fn into_inner(self) -> T {
+        match self {
+            SendError::RxClosed(value) => value,
+        }
+    }
+}
+
+impl<T> fmt::Debug for SendError<T> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            SendError::RxClosed(_) => fmt.debug_tuple("RxClosed").finish_non_exhaustive(),
+        }
+    }
+}
+
+impl<T> fmt::Display for SendError<T> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            SendError::RxClosed(_) => "sending on a closed channel".fmt(fmt),
+        }
+    }
+}
+
+impl<T> Error for SendError<T> {}
+
+pub(crate) struct Rx<T, S, E = ()>
 where
     S: Semaphore,
 {
-    chan: Rc<Chan<T, S>>,
+    chan: Rc<Chan<T, S, E>>,
 }
 
-impl<T, S> Tx<T, S>
+impl<T, S, E> Tx<T, S, E>
 where
     S: Semaphore,
 {
-    pub(crate) fn new(chan: Rc<Chan<T, S>>) -> Self {
+    pub(crate) fn new(chan: Rc<Chan<T, S, E>>) -> Self {
         chan.tx_count.set(chan.tx_count.get() + 1);
-        Self { chan }
+        Self {
+            chan,
+            closed_waiter: None,
+        }
     }
 
     // caller must make sure the chan has spaces
-    pub(crate) fn send(&self, value: T) -> Result<(), SendError> {
+    pub(crate) fn send(&self, value: T) -> Result<(), SendError<T>> {
+        let weight = self.chan.weight(&value);
+        self.send_weighted(value, weight)
+    }
+
+    /// Send a value which took `weight` permits, the same number is given
+    /// back when it is received.
+    pub(crate) fn send_weighted(&self, value: T, weight: u32) -> Result<(), SendError<T>> {
         // check if the semaphore is closed
         if self.chan.semaphore.is_closed() {
-            return Err(SendError::RxClosed);
+            return Err(SendError::RxClosed(value));
         }
 
         // put data into the queue
+        let queued = self.chan.queued_weight.get() + weight as usize;
+        self.chan.queued_weight.set(queued);
+        if let Some((_, high)) = self.chan.watermarks {
+            if queued > high && !self.chan.over_high.replace(true) {
+                self.chan.tx_waiters.notify_waiters();
+            }
+        }
         unsafe {
-            self.chan.queue.borrow_mut().push_unchecked(value);
+            self.chan.queue.borrow_mut().push_unchecked((value, weight));
         }
         // if rx waker is set, wake it
         if let Some(w) = self.chan.rx_waker.replace(None) {
@@ -126,13 +387,76 @@ where
     }
 
     pub(crate) fn close(&self) {
-        self.chan.semaphore.close();
+        self.chan.close();
+        // nothing can be sent anymore, so a pending recv may have to end
+        if let Some(rx_waker) = self.chan.rx_waker.take() {
+            rx_waker.wake();
+        }
+    }
+
+    /// Close the channel for all senders. The receiver gets the reason once
+    /// it has received all queued values. Only the first reason is kept.
+    pub(crate) fn close_with(&self, reason: E) {
+        self.chan.close();
+        if self.chan.aborted.replace(true) {
+            return;
+        }
+        *self.chan.reason.borrow_mut() = Some(reason);
+        if let Some(rx_waker) = self.chan.rx_waker.take() {
+            rx_waker.wake();
+        }
     }
 
     pub(crate) fn is_closed(&self) -> bool {
         self.chan.semaphore.is_closed()
     }
 
+    pub(crate) fn flush(&self) {
+        self.chan.flush.set(true);
+        if let Some(rx_waker) = self.chan.rx_waker.take() {
+            rx_waker.wake();
+        }
+    }
+
+    /// Returns the number of permits `value` takes.
+    pub(crate) fn weight(&self, value: &T) -> u32 {
+        self.chan.weight(value)
+    }
+
+    pub(crate) async fn closed(&self) {
+        while !self.is_closed() {
+            self.chan.tx_waiters.notified().await;
+        }
+    }
+
+    pub(crate) fn is_over_high_watermark(&self) -> bool {
+        self.chan.over_high.get()
+    }
+
+    pub(crate) async fn wait_over_high_watermark(&self) {
+        while !self.chan.over_high.get() && !self.is_closed() {
+            self.chan.tx_waiters.notified().await;
+        }
+    }
+
+    pub(crate) async fn wait_below_low_watermark(&self) {
+        while self.chan.over_high.get() && !self.is_closed() {
+            self.chan.tx_waiters.notified().await;
+        }
+    }
+
+    pub(crate) fn poll_closed(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        if self.is_closed() {
+            return Poll::Ready(());
+        }
+        let waiter = self
+            .closed_waiter
+            .get_or_insert_with(|| Box::pin(Waiter::new()));
+        // Safety: the waiter is removed in `Drop for Tx`.
+        unsafe { self.chan.tx_waiters.register(waiter.as_mut(), cx.waker()) };
+        Poll::Pending
+    }
+
     /// Returns `true` if senders belong to the same channel.
     pub(crate) fn same_channel(&self, other: &Self) -> bool {
         Rc::ptr_eq(&self.chan, &other.chan)
@@ -143,7 +467,7 @@ where
     }
 }
 
-impl<T, S> Clone for Tx<T, S>
+impl<T, S, E> Clone for Tx<T, S, E>
 where
     S: Semaphore,
 {
@@ -151,20 +475,31 @@ where
         self.chan.tx_count.set(self.chan.tx_count.get() + 1);
         Self {
             chan: self.chan.clone(),
+            closed_waiter: None,
         }
     }
 }
 
-impl<T, S> Drop for Tx<T, S>
+impl<T, S, E> Drop for Tx<T, S, E>
 where
     S: Semaphore,
 {
     fn drop(&mut self) {
+        if let Some(waiter) = self.closed_waiter.as_mut() {
+            self.chan.tx_waiters.remove(waiter.as_mut());
+        }
+
         let cnt = self.chan.tx_count.get();
         self.chan.tx_count.set(cnt - 1);
 
-        if cnt == 1 {
-            self.chan.semaphore.close();
+        let poison = std::thread::panicking()
+            && (cnt == 1 || self.chan.panic_policy.get() == PanicPolicy::AnySender);
+        if poison {
+            self.chan.poisoned.set(true);
+        }
+        if cnt == 1 || poison {
+            self.chan.close();
+            // the receiver ends once it has received the queued values
             if let Some(rx_waker) = self.chan.rx_waker.take() {
                 rx_waker.wake();
             }
@@ -172,23 +507,24 @@ where
     }
 }
 
-impl<T, S> Rx<T, S>
+impl<T, S, E> Rx<T, S, E>
 where
     S: Semaphore,
 {
-    pub(crate) fn new(chan: Rc<Chan<T, S>>) -> Self {
+    pub(crate) fn new(chan: Rc<Chan<T, S, E>>) -> Self {
         Self { chan }
     }
 
-    pub(crate) fn try_recv(&mut self) -> Result<T, TryRecvError> {
+    pub(crate) fn try_recv(&mut self) -> Result<T, TryRecvError<E>> {
         let mut queue = self.chan.queue.borrow_mut();
         if !queue.is_empty() {
-            let val = unsafe { queue.pop_unchecked() };
-            self.chan.semaphore.add_permits(1);
+            let (val, weight) = unsafe { queue.pop_unchecked() };
+            self.chan.release(weight as usize);
             return Ok(val);
         }
-        if self.chan.tx_count.get() == 0 {
-            Err(TryRecvError::Disconnected)
+        drop(queue);
+        if self.chan.is_disconnected() {
+            Err(self.close_error().into())
         } else {
             Err(TryRecvError::Empty)
         }
@@ -197,13 +533,96 @@ where
     pub(crate) fn recv(&mut self, cx: &mut Context<'_>) -> Poll<Option<T>> {
         let mut queue = self.chan.queue.borrow_mut();
         if !queue.is_empty() {
-            let val = unsafe { queue.pop_unchecked() };
-            self.chan.semaphore.add_permits(1);
+            let (val, weight) = unsafe { queue.pop_unchecked() };
+            self.chan.release(weight as usize);
             return Poll::Ready(Some(val));
         }
-        if self.chan.tx_count.get() == 0 {
+        if self.chan.is_disconnected() {
             return Poll::Ready(None);
         }
+        self.register_waker(cx);
+        Poll::Pending
+    }
+
+    pub(crate) fn try_recv_matching<F>(&mut self, mut f: F) -> Result<T, TryRecvError>
+    where
+        F: FnMut(&T) -> bool,
+    {
+        let mut queue = self.chan.queue.borrow_mut();
+        if let Some((val, weight)) = queue.remove_first(0, |(val, _)| f(val)) {
+            self.chan.release(weight as usize);
+            return Ok(val);
+        }
+        if self.chan.is_disconnected() {
+            Err(TryRecvError::Disconnected)
+        } else {
+            Err(TryRecvError::Empty)
+        }
+    }
+
+    /// Receive the first value matching `f`.
+    ///
+    /// `scanned` is the number of values at the head of the queue which are
+    /// already known not to match, so that only newly arrived values are
+    /// checked on later polls. The caller must reset it when `f` changes.
+    pub(crate) fn recv_matching<F>(
+        &mut self,
+        cx: &mut Context<'_>,
+        scanned: &mut usize,
+        f: &mut F,
+    ) -> Poll<Option<T>>
+    where
+        F: FnMut(&T) -> bool,
+    {
+        let mut queue = self.chan.queue.borrow_mut();
+        if let Some((val, weight)) = queue.remove_first(*scanned, |(val, _)| f(val)) {
+            self.chan.release(weight as usize);
+            return Poll::Ready(Some(val));
+        }
+        *scanned = queue.len();
+        if self.chan.is_disconnected() {
+            return Poll::Ready(None);
+        }
+        self.register_waker(cx);
+        Poll::Pending
+    }
+
+    /// Move values into `buf` until the batch is full, a sender flushes, or
+    /// the channel is disconnected. Returns the number of values in the batch,
+    /// which is 0 once the channel is disconnected and empty.
+    pub(crate) fn recv_batch(
+        &mut self,
+        cx: &mut Context<'_>,
+        buf: &mut Vec<T>,
+        batch: &mut Batch,
+    ) -> Poll<usize> {
+        let mut queue = self.chan.queue.borrow_mut();
+        while !batch.is_full() && !queue.is_empty() {
+            let (val, weight) = unsafe { queue.pop_unchecked() };
+            self.chan.release(weight as usize);
+            batch.items += 1;
+            batch.weight += weight as usize;
+            buf.push(val);
+        }
+        drop(queue);
+
+        // a flush while nothing is collected yet ends the next batch as soon
+        // as it has a value
+        let flush = batch.items > 0 && self.chan.flush.get();
+        if batch.is_full() || flush || self.chan.is_disconnected() {
+            if batch.items > 0 {
+                self.chan.flush.set(false);
+            }
+            let items = batch.items;
+            batch.items = 0;
+            batch.weight = 0;
+            return Poll::Ready(items);
+        }
+        self.register_waker(cx);
+        Poll::Pending
+    }
+
+    fn register_waker(&self, cx: &mut Context<'_>) {
         let mut borrowed = self.chan.rx_waker.borrow_mut();
         match borrowed.as_mut() {
             Some(inner) => {
@@ -215,44 +634,145 @@ where
                 *borrowed = Some(cx.waker().clone());
             }
         }
-        Poll::Pending
+    }
+
+    pub(crate) fn peek(&self) -> Option<Ref<'_, T>> {
+        Ref::filter_map(self.chan.queue.borrow(), |queue| {
+            queue.peek().map(|(val, _)| val)
+        })
+        .ok()
+    }
+
+    pub(crate) fn drain(&mut self) -> impl Iterator<Item = T> + '_ {
+        std::iter::from_fn(move || {
+            let mut queue = self.chan.queue.borrow_mut();
+            if queue.is_empty() {
+                return None;
+            }
+            let (val, weight) = unsafe { queue.pop_unchecked() };
+            drop(queue);
+            self.chan.release(weight as usize);
+            Some(val)
+        })
+    }
+
+    /// Removes the values for which `f` returns `false`. They are dropped and
+    /// their permits released once the queue is no longer borrowed, also if
+    /// `f` panics.
+    pub(crate) fn retain<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&T) -> bool,
+    {
+        let mut removed = Removed {
+            chan: &self.chan,
+            values: Vec::new(),
+        };
+        self.chan
+            .queue
+            .borrow_mut()
+            .retain(&mut removed.values, |(val, _)| f(val));
     }
 
     pub(crate) fn close(&self) {
-        self.chan.semaphore.close();
+        self.chan.close();
     }
 
     pub(crate) fn is_closed(&self) -> bool {
         self.chan.semaphore.is_closed()
     }
 
+    pub(crate) fn set_panic_policy(&self, policy: PanicPolicy) {
+        self.chan.panic_policy.set(policy);
+    }
+
+    pub(crate) fn is_poisoned(&self) -> bool {
+        self.chan.poisoned.get()
+    }
+
     pub(crate) fn hint(&self) -> usize {
         self.chan.queue.borrow().len()
     }
+
+    pub(crate) fn queued_weight(&self) -> usize {
+        self.chan.queued_weight.get()
+    }
+}
+
+/// Receiver which yields values in batches, see `bounded::Rx::batched` and
+/// `unbounded::Rx::batched`.
+pub struct Batched<R> {
+    rx: R,
+    batch: Batch,
+}
+
+impl<R> Batched<R> {
+    pub(crate) fn new(rx: R, max_items: usize) -> Self {
+        assert!(max_items > 0, "batch size must be greater than 0");
+        Self {
+            rx,
+            batch: Batch::new(max_items),
+        }
+    }
+
+    pub(crate) fn set_max_weight(&mut self, max_weight: usize) {
+        self.batch.max_weight = max_weight;
+    }
+
+    pub(crate) fn parts_mut(&mut self) -> (&mut R, &mut Batch) {
+        (&mut self.rx, &mut self.batch)
+    }
+
+    pub fn into_inner(self) -> R {
+        self.rx
+    }
+}
+
+impl<T, S, E> Rx<T, S, E>
+where
+    S: Semaphore,
+{
+    pub(crate) fn recv_result(&mut self, cx: &mut Context<'_>) -> Poll<Result<T, RecvError<E>>> {
+        match self.recv(cx) {
+            Poll::Ready(Some(val)) => Poll::Ready(Ok(val)),
+            Poll::Ready(None) => Poll::Ready(Err(self.close_error())),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+
+    /// Takes the reason the channel was closed with, so it is only returned
+    /// once and `E` need not be `Clone`.
+    fn close_error(&self) -> RecvError<E> {
+        let reason = self.chan.reason.borrow_mut().take();
+        match reason {
+            Some(reason) => RecvError::Closed(reason),
+            None if self.chan.poisoned.get() => RecvError::SenderPanicked,
+            None => RecvError::Disconnected,
+        }
+    }
 }
 
-impl<T, S> Drop for Rx<T, S>
+impl<T, S, E> Drop for Rx<T, S, E>
 where
     S: Semaphore,
 {
     fn drop(&mut self) {
         // close semaphore on close, this will make tx send await return.
-        self.chan.semaphore.close();
+        self.chan.close();
         // consume all elements
         let mut queue = self.chan.queue.borrow_mut();
-        let len = queue.len();
         while !queue.is_empty() {
-            drop(unsafe { queue.pop_unchecked() });
+            let (_, weight) = unsafe { queue.pop_unchecked() };
+            self.chan.release(weight as usize);
         }
-        self.chan.semaphore.add_permits(len);
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::channel;
+    use super::{channel, channel_with_reason, PanicPolicy, RecvError, TryRecvError};
     use crate::semaphore::Inner;
     use futures_lite::future::poll_fn;
+    use std::panic::{catch_unwind, AssertUnwindSafe};
 
     #[monoio::test]
     async fn test_chan() {
@@ -265,4 +785,78 @@ mod tests {
         rx.close();
         assert!(tx.is_closed());
     }
+
+    #[monoio::test]
+    async fn test_chan_close_with() {
+        let semaphore = Inner::new(2);
+        let (tx, mut rx) = channel_with_reason::<u32, _, &str>(semaphore);
+        let tx2 = tx.clone();
+        assert!(tx.send(1).is_ok());
+        tx.close_with("reset");
+        tx2.close_with("ignored");
+        assert!(tx2.send(2).is_err());
+
+        assert_eq!(rx.try_recv(), Ok(1));
+        assert_eq!(
+            poll_fn(|cx| rx.recv_result(cx)).await,
+            Err(RecvError::Closed("reset"))
+        );
+        // the reason is moved out once
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Disconnected));
+    }
+
+    fn drop_panicking<T>(value: T) {
+        let _ = catch_unwind(AssertUnwindSafe(move || {
+            let _value = value;
+            panic!("producer crashed");
+        }));
+    }
+
+    #[monoio::test]
+    async fn test_chan_sender_panicked() {
+        // the last sender panicking poisons the channel
+        let (tx, mut rx) = channel::<u32, _>(Inner::new(2));
+        let tx2 = tx.clone();
+        assert!(tx.send(1).is_ok());
+        drop_panicking(tx);
+        assert!(!rx.is_poisoned());
+        assert!(tx2.send(2).is_ok());
+        drop_panicking(tx2);
+        assert!(rx.is_poisoned());
+
+        assert_eq!(rx.try_recv(), Ok(1));
+        assert_eq!(rx.try_recv(), Ok(2));
+        assert_eq!(
+            poll_fn(|cx| rx.recv_result(cx)).await,
+            Err(RecvError::SenderPanicked)
+        );
+    }
+
+    #[monoio::test]
+    async fn test_chan_any_sender_panicked() {
+        let (tx, mut rx) = channel::<u32, _>(Inner::new(2));
+        rx.set_panic_policy(PanicPolicy::AnySender);
+        let tx2 = tx.clone();
+        assert!(tx.send(1).is_ok());
+        drop_panicking(tx);
+        assert!(rx.is_poisoned());
+        assert!(tx2.send(2).is_err());
+
+        assert_eq!(rx.try_recv(), Ok(1));
+        assert_eq!(rx.try_recv(), Err(TryRecvError::SenderPanicked));
+    }
+
+    #[monoio::test]
+    async fn test_chan_close_with_not_clone() {
+        #[derive(Debug, PartialEq)]
+        struct Reset;
+
+        let (tx, mut rx) = channel_with_reason::<u32, _, Reset>(Inner::new(1));
+        tx.close_with(Reset);
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Closed(Reset)));
+        assert_eq!(
+            poll_fn(|cx| rx.recv_result(cx)).await,
+            Err(RecvError::Disconnected)
+        );
+    }
 }
@@ -0,0 +1,282 @@
+//! Bounded channel which keeps only the latest value per key.
+//!
+//! Sending a value for a key which is still queued replaces the queued value
+//! in place, so the key keeps its position and the receiver only sees the
+//! newest value. Capacity is counted in distinct queued keys.
+
+use super::chan::{SendError, TryRecvError};
+use crate::semaphore::Inner;
+use futures_lite::future::poll_fn;
+use std::{
+    cell::{Cell, RefCell},
+    collections::{hash_map::Entry, HashMap, VecDeque},
+    hash::Hash,
+    rc::Rc,
+    task::{Context, Poll, Waker},
+};
+
+pub struct Tx<K, V> {
+    chan: Rc<Chan<K, V>>,
+}
+
+pub struct Rx<K, V> {
+    chan: Rc<Chan<K, V>>,
+}
+
+pub fn channel<K, V>(buffer: usize) -> (Tx<K, V>, Rx<K, V>)
+where
+    K: Hash + Eq + Clone,
+{
+    let chan = Rc::new(Chan {
+        values: RefCell::new(HashMap::new()),
+        order: RefCell::new(VecDeque::new()),
+        semaphore: Inner::new(buffer),
+        rx_waker: RefCell::new(None),
+        tx_count: Cell::new(1),
+    });
+    let tx = Tx { chan: chan.clone() };
+    let rx = Rx { chan };
+    (tx, rx)
+}
+
+struct Chan<K, V> {
+    values: RefCell<HashMap<K, V>>,
+    /// Queued keys in the order they are received.
+    order: RefCell<VecDeque<K>>,
+    semaphore: Inner,
+    rx_waker: RefCell<Option<Waker>>,
+    tx_count: Cell<usize>,
+}
+
+impl<K, V> Chan<K, V> {
+    fn close(&self) {
+        self.semaphore.close();
+        self.wake_receiver();
+    }
+
+    fn wake_receiver(&self) {
+        if let Some(w) = self.rx_waker.take() {
+            w.wake();
+        }
+    }
+}
+
+impl<K, V> Tx<K, V>
+where
+    K: Hash + Eq + Clone,
+{
+    /// Sends a value for `key`. If a value for `key` is still queued it is
+    /// replaced and dropped, otherwise this waits for a free slot.
+    pub async fn send(&self, key: K, value: V) -> Result<(), SendError<(K, V)>> {
+        if self.chan.semaphore.is_closed() {
+            return Err(SendError::RxClosed((key, value)));
+        }
+        let replaced = match self.chan.values.borrow_mut().get_mut(&key) {
+            Some(queued) => Ok(std::mem::replace(queued, value)),
+            None => Err(value),
+        };
+        let value = match replaced {
+            // dropped once `values` is no longer borrowed
+            Ok(replaced) => {
+                drop(replaced);
+                return Ok(());
+            }
+            Err(value) => value,
+        };
+
+        // acquire semaphore first
+        if self.chan.semaphore.acquire(1).await.is_err() {
+            return Err(SendError::RxClosed((key, value)));
+        }
+        let replaced = match self.chan.values.borrow_mut().entry(key) {
+            Entry::Occupied(mut entry) => {
+                // another sender queued the key while we were waiting
+                self.chan.semaphore.release(1);
+                Some(entry.insert(value))
+            }
+            Entry::Vacant(entry) => {
+                self.chan.order.borrow_mut().push_back(entry.key().clone());
+                entry.insert(value);
+                self.chan.wake_receiver();
+                None
+            }
+        };
+        drop(replaced);
+        Ok(())
+    }
+
+    pub fn close(&self) {
+        self.chan.close()
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.chan.semaphore.is_closed()
+    }
+
+    pub fn same_channel(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.chan, &other.chan)
+    }
+
+    pub fn hint(&self) -> usize {
+        self.chan.order.borrow().len()
+    }
+}
+
+impl<K, V> Clone for Tx<K, V> {
+    fn clone(&self) -> Self {
+        self.chan.tx_count.set(self.chan.tx_count.get() + 1);
+        Self {
+            chan: self.chan.clone(),
+        }
+    }
+}
+
+impl<K, V> Drop for Tx<K, V> {
+    fn drop(&mut self) {
+        let cnt = self.chan.tx_count.get();
+        self.chan.tx_count.set(cnt - 1);
+
+        if cnt == 1 {
+            self.chan.close();
+        }
+    }
+}
+
+impl<K, V> Rx<K, V>
+where
+    K: Hash + Eq,
+{
+    /// Receives the key queued first with its latest value.
+    pub async fn recv(&mut self) -> Option<(K, V)> {
+        poll_fn(|cx| self.poll_recv(cx)).await
+    }
+
+    pub fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<Option<(K, V)>> {
+        match self.try_recv() {
+            Ok(val) => return Poll::Ready(Some(val)),
+            Err(TryRecvError::Empty) => {}
+            Err(_) => return Poll::Ready(None),
+        }
+        let mut borrowed = self.chan.rx_waker.borrow_mut();
+        match borrowed.as_mut() {
+            Some(inner) => {
+                if !inner.will_wake(cx.waker()) {
+                    *inner = cx.waker().clone();
+                }
+            }
+            None => {
+                *borrowed = Some(cx.waker().clone());
+            }
+        }
+        Poll::Pending
+    }
+
+    pub fn try_recv(&mut self) -> Result<(K, V), TryRecvError> {
+        let key = self.chan.order.borrow_mut().pop_front();
+        if let Some(key) = key {
+            let value = self
+                .chan
+                .values
+                .borrow_mut()
+                .remove(&key)
+                .expect("queued key has no value");
+            self.chan.semaphore.release(1);
+            return Ok((key, value));
+        }
+        if self.chan.tx_count.get() == 0 || self.chan.semaphore.is_closed() {
+            Err(TryRecvError::Disconnected)
+        } else {
+            Err(TryRecvError::Empty)
+        }
+    }
+
+    /// Closes the channel without dropping the receiver. Values already
+    /// queued can still be received.
+    pub fn close(&self) {
+        self.chan.close()
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.chan.semaphore.is_closed()
+    }
+
+    pub fn hint(&self) -> usize {
+        self.chan.order.borrow().len()
+    }
+}
+
+impl<K, V> Drop for Rx<K, V> {
+    fn drop(&mut self) {
+        // close the channel, this will make tx send await return.
+        self.chan.close();
+        // consume all elements, dropping them once they are no longer borrowed
+        let order = std::mem::take(&mut *self.chan.order.borrow_mut());
+        let values = std::mem::take(&mut *self.chan.values.borrow_mut());
+        self.chan.semaphore.release(order.len());
+        drop(order);
+        drop(values);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{channel, Rx};
+    use crate::mpsc::SendError;
+    use std::{cell::RefCell, rc::Rc};
+
+    #[monoio::test]
+    async fn test_coalesce_channel() {
+        let (tx, mut rx) = channel(2);
+        tx.send("a", 1).await.unwrap();
+        tx.send("b", 1).await.unwrap();
+        // replacing a queued value does not need a free slot
+        tx.send("a", 2).await.unwrap();
+        tx.send("b", 2).await.unwrap();
+        assert_eq!(tx.hint(), 2);
+
+        let tx2 = tx.clone();
+        let join = monoio::spawn(async move {
+            tx2.send("c", 1).await.unwrap();
+            tx2.send("a", 3).await.unwrap();
+        });
+        assert_eq!(rx.recv().await, Some(("a", 2)));
+        assert_eq!(rx.recv().await, Some(("b", 2)));
+        join.await;
+        assert_eq!(rx.recv().await, Some(("c", 1)));
+        assert_eq!(rx.recv().await, Some(("a", 3)));
+
+        rx.close();
+        assert_eq!(tx.send("a", 4).await, Err(SendError::RxClosed(("a", 4))));
+        assert_eq!(rx.recv().await, None);
+    }
+
+    /// Receives from the channel when it is dropped.
+    struct Probe(u32, Rc<RefCell<Option<Rx<&'static str, Probe>>>>);
+
+    impl Drop for Probe {
+        fn drop(&mut self) {
+            if let Some(rx) = self.1.borrow_mut().as_mut() {
+                let _ = rx.try_recv();
+            }
+        }
+    }
+
+    #[monoio::test]
+    async fn test_coalesce_channel_drop_replaced() {
+        let (tx, rx) = channel(2);
+        let shared = Rc::new(RefCell::new(None));
+        tx.send("a", Probe(1, shared.clone())).await.unwrap();
+        tx.send("b", Probe(1, Rc::default())).await.unwrap();
+        *shared.borrow_mut() = Some(rx);
+
+        // the replaced value is dropped after the values are released, so it
+        // can receive "a" again
+        tx.send("a", Probe(2, Rc::default())).await.unwrap();
+        assert_eq!(tx.hint(), 1);
+        let mut rx = shared.borrow_mut().take().unwrap();
+        assert_eq!(
+            rx.try_recv().map(|(key, probe)| (key, probe.0)),
+            Ok(("b", 1))
+        );
+    }
+}
@@ -0,0 +1,356 @@
+//! Bounded channel which is fair between senders.
+//!
+//! Every **Sender** handle, including each clone, has its own queue. The
+//! receiver serves the senders with queued values in turn using deficit round
+//! robin: in each turn a sender may deliver as many values as its weight, so
+//! a busy sender can not delay the others once their values are queued.
+//!
+//! Fairness only applies to receiving. Capacity is one budget shared by all
+//! senders and handed out first come first served, so a busy sender can still
+//! take most of the free slots and make the others wait to send.
+
+use super::chan::{SendError, TryRecvError};
+use crate::semaphore::Inner;
+use futures_lite::future::poll_fn;
+use std::{
+    cell::{Cell, RefCell},
+    collections::VecDeque,
+    rc::Rc,
+    task::{Context, Poll, Waker},
+};
+
+pub struct Tx<T> {
+    chan: Rc<Chan<T>>,
+    lane: usize,
+}
+
+pub struct Rx<T> {
+    chan: Rc<Chan<T>>,
+}
+
+pub fn channel<T>(buffer: usize) -> (Tx<T>, Rx<T>) {
+    let chan = Rc::new(Chan {
+        state: RefCell::new(State {
+            lanes: Vec::new(),
+            free: Vec::new(),
+            active: VecDeque::new(),
+            len: 0,
+        }),
+        semaphore: Inner::new(buffer),
+        rx_waker: RefCell::new(None),
+        tx_count: Cell::new(0),
+    });
+    let tx = Tx::new(chan.clone());
+    let rx = Rx { chan };
+    (tx, rx)
+}
+
+struct Chan<T> {
+    state: RefCell<State<T>>,
+    semaphore: Inner,
+    rx_waker: RefCell<Option<Waker>>,
+    tx_count: Cell<usize>,
+}
+
+struct State<T> {
+    lanes: Vec<Lane<T>>,
+    /// Indexes of lanes which can be reused.
+    free: Vec<usize>,
+    /// Lanes with queued values, in the order they are served.
+    active: VecDeque<usize>,
+    /// Number of values in all lanes.
+    len: usize,
+}
+
+/// The queue of a single sender.
+struct Lane<T> {
+    queue: VecDeque<T>,
+    weight: usize,
+    /// Values the lane may still deliver in its current turn.
+    deficit: usize,
+    /// Whether the sender owning the lane is alive.
+    attached: bool,
+}
+
+impl<T> State<T> {
+    fn attach(&mut self) -> usize {
+        let lane = Lane {
+            queue: VecDeque::new(),
+            weight: 1,
+            deficit: 0,
+            attached: true,
+        };
+        match self.free.pop() {
+            Some(idx) => {
+                self.lanes[idx] = lane;
+                idx
+            }
+            None => {
+                self.lanes.push(lane);
+                self.lanes.len() - 1
+            }
+        }
+    }
+
+    fn detach(&mut self, idx: usize) {
+        let lane = &mut self.lanes[idx];
+        lane.attached = false;
+        // a lane with queued values is freed once it has been drained.
+        if lane.queue.is_empty() {
+            self.free.push(idx);
+        }
+    }
+
+    fn push(&mut self, idx: usize, value: T) {
+        let lane = &mut self.lanes[idx];
+        if lane.queue.is_empty() {
+            self.active.push_back(idx);
+        }
+        lane.queue.push_back(value);
+        self.len += 1;
+    }
+
+    fn pop(&mut self) -> Option<T> {
+        let idx = *self.active.front()?;
+        let lane = &mut self.lanes[idx];
+        if lane.deficit == 0 {
+            // start of a new turn
+            lane.deficit = lane.weight;
+        }
+        let value = lane.queue.pop_front().expect("active lane is empty");
+        lane.deficit -= 1;
+        self.len -= 1;
+
+        if lane.queue.is_empty() {
+            // an idle lane does not keep its remaining turn
+            lane.deficit = 0;
+            self.active.pop_front();
+            if !lane.attached {
+                self.free.push(idx);
+            }
+        } else if lane.deficit == 0 {
+            self.active.rotate_left(1);
+        }
+        Some(value)
+    }
+}
+
+impl<T> Chan<T> {
+    fn close(&self) {
+        self.semaphore.close();
+        self.wake_receiver();
+    }
+
+    fn wake_receiver(&self) {
+        if let Some(w) = self.rx_waker.take() {
+            w.wake();
+        }
+    }
+}
+
+impl<T> Tx<T> {
+    fn new(chan: Rc<Chan<T>>) -> Self {
+        chan.tx_count.set(chan.tx_count.get() + 1);
+        let lane = chan.state.borrow_mut().attach();
+        Self { chan, lane }
+    }
+
+    pub async fn send(&self, value: T) -> Result<(), SendError<T>> {
+        // acquire semaphore first
+        if self.chan.semaphore.acquire(1).await.is_err() {
+            return Err(SendError::RxClosed(value));
+        }
+        self.chan.state.borrow_mut().push(self.lane, value);
+        self.chan.wake_receiver();
+        Ok(())
+    }
+
+    /// Sets how many values this sender may deliver in each turn, relative
+    /// to the other senders. The default weight is 1, also for clones.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `weight` is 0.
+    pub fn set_weight(&self, weight: usize) {
+        assert!(weight > 0, "sender weight must be greater than 0");
+        self.chan.state.borrow_mut().lanes[self.lane].weight = weight;
+    }
+
+    pub fn close(&self) {
+        self.chan.close()
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.chan.semaphore.is_closed()
+    }
+
+    pub fn same_channel(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.chan, &other.chan)
+    }
+
+    pub fn hint(&self) -> usize {
+        self.chan.state.borrow().len
+    }
+}
+
+impl<T> Clone for Tx<T> {
+    fn clone(&self) -> Self {
+        Self::new(self.chan.clone())
+    }
+}
+
+impl<T> Drop for Tx<T> {
+    fn drop(&mut self) {
+        self.chan.state.borrow_mut().detach(self.lane);
+
+        let cnt = self.chan.tx_count.get();
+        self.chan.tx_count.set(cnt - 1);
+
+        if cnt == 1 {
+            self.chan.close();
+        }
+    }
+}
+
+impl<T> Rx<T> {
+    pub async fn recv(&mut self) -> Option<T> {
+        poll_fn(|cx| self.poll_recv(cx)).await
+    }
+
+    pub fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        match self.try_recv() {
+            Ok(val) => return Poll::Ready(Some(val)),
+            Err(TryRecvError::Empty) => {}
+            Err(_) => return Poll::Ready(None),
+        }
+        let mut borrowed = self.chan.rx_waker.borrow_mut();
+        match borrowed.as_mut() {
+            Some(inner) => {
+                if !inner.will_wake(cx.waker()) {
+                    *inner = cx.waker().clone();
+                }
+            }
+            None => {
+                *borrowed = Some(cx.waker().clone());
+            }
+        }
+        Poll::Pending
+    }
+
+    pub fn try_recv(&mut self) -> Result<T, TryRecvError> {
+        let val = self.chan.state.borrow_mut().pop();
+        if let Some(val) = val {
+            self.chan.semaphore.release(1);
+            return Ok(val);
+        }
+        if self.chan.tx_count.get() == 0 || self.chan.semaphore.is_closed() {
+            Err(TryRecvError::Disconnected)
+        } else {
+            Err(TryRecvError::Empty)
+        }
+    }
+
+    /// Closes the channel without dropping the receiver. Values already
+    /// queued can still be received.
+    pub fn close(&self) {
+        self.chan.close()
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.chan.semaphore.is_closed()
+    }
+
+    pub fn hint(&self) -> usize {
+        self.chan.state.borrow().len
+    }
+}
+
+impl<T> Drop for Rx<T> {
+    fn drop(&mut self) {
+        // close the channel, this will make tx send await return.
+        self.chan.close();
+        // consume all elements, each is dropped outside the borrow since it
+        // may hold a sender of this channel
+        loop {
+            let val = self.chan.state.borrow_mut().pop();
+            match val {
+                Some(val) => {
+                    drop(val);
+                    self.chan.semaphore.release(1);
+                }
+                None => break,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::channel;
+
+    #[monoio::test]
+    async fn test_fair_channel() {
+        let (hot, mut rx) = channel(16);
+        let cold = hot.clone();
+        for i in 0..6 {
+            hot.send(("hot", i)).await.unwrap();
+        }
+        cold.send(("cold", 0)).await.unwrap();
+        cold.send(("cold", 1)).await.unwrap();
+        assert_eq!(rx.hint(), 8);
+
+        let expected = [
+            ("hot", 0),
+            ("cold", 0),
+            ("hot", 1),
+            ("cold", 1),
+            ("hot", 2),
+            ("hot", 3),
+        ];
+        for val in expected {
+            assert_eq!(rx.recv().await, Some(val));
+        }
+
+        // a dropped sender's values are still delivered
+        drop(hot);
+        assert_eq!(rx.recv().await, Some(("hot", 4)));
+        assert_eq!(rx.recv().await, Some(("hot", 5)));
+        drop(cold);
+        assert_eq!(rx.recv().await, None);
+    }
+
+    #[monoio::test]
+    async fn test_fair_channel_weight() {
+        let (a, mut rx) = channel(16);
+        let b = a.clone();
+        a.set_weight(3);
+        for i in 0..6 {
+            a.send(('a', i)).await.unwrap();
+            b.send(('b', i)).await.unwrap();
+        }
+        let order: String = (0..8).map(|_| rx.try_recv().unwrap().0).collect();
+        assert_eq!(order, "aaabaaab");
+
+        // lanes of dropped senders are reused
+        drop(a);
+        let c = b.clone();
+        c.send(('c', 0)).await.unwrap();
+        let order: String = std::iter::from_fn(|| rx.try_recv().ok())
+            .map(|v| v.0)
+            .collect();
+        assert_eq!(order, "bcbbb");
+    }
+
+    struct Msg {
+        _tx: super::Tx<Msg>,
+    }
+
+    #[monoio::test]
+    async fn test_fair_channel_drop_sender_in_value() {
+        let (tx, rx) = channel(2);
+        tx.send(Msg { _tx: tx.clone() }).await.unwrap();
+        // dropping the queued sender touches the channel state
+        drop(rx);
+        assert!(tx.is_closed());
+    }
+}
@@ -1,8 +1,17 @@
-mod block;
+pub(crate) mod block;
 mod chan;
 mod semaphore;
 
+pub mod ack;
+pub mod array;
 pub mod bounded;
+pub mod coalesce;
+pub mod fair;
+pub mod priority;
+pub mod router;
+pub mod rx_set;
+pub mod sequenced;
+pub mod tee;
 pub mod unbounded;
 
-pub use chan::{SendError, TryRecvError};
+pub use chan::{Batched, PanicPolicy, RecvError, SendError, TryRecvError};
@@ -0,0 +1,283 @@
+//! Bounded channel which delivers values by priority.
+//!
+//! Values are kept in a binary heap ordered by priority, so a high priority
+//! value overtakes everything of lower priority which is already queued.
+//! Values of the same priority are received in send order, or in reverse send
+//! order for a channel created with [`lifo_channel`]. Capacity is shared by
+//! all priorities.
+
+use super::chan::{SendError, TryRecvError};
+use crate::semaphore::Inner;
+use futures_lite::future::poll_fn;
+use std::{
+    cell::{Cell, RefCell},
+    cmp::Ordering,
+    collections::BinaryHeap,
+    rc::Rc,
+    task::{Context, Poll, Waker},
+};
+
+pub struct Tx<T> {
+    chan: Rc<Chan<T>>,
+}
+
+pub struct Rx<T> {
+    chan: Rc<Chan<T>>,
+}
+
+/// Creates a priority channel where values of the same priority are received
+/// first in, first out.
+pub fn channel<T>(buffer: usize) -> (Tx<T>, Rx<T>) {
+    new_channel(buffer, false)
+}
+
+/// Creates a priority channel where values of the same priority are received
+/// last in, first out. If all values are sent with the same priority the
+/// channel behaves as a stack.
+pub fn lifo_channel<T>(buffer: usize) -> (Tx<T>, Rx<T>) {
+    new_channel(buffer, true)
+}
+
+fn new_channel<T>(buffer: usize, lifo: bool) -> (Tx<T>, Rx<T>) {
+    let chan = Rc::new(Chan {
+        heap: RefCell::new(BinaryHeap::new()),
+        seq: Cell::new(0),
+        lifo,
+        semaphore: Inner::new(buffer),
+        rx_waker: RefCell::new(None),
+        tx_count: Cell::new(1),
+    });
+    let tx = Tx { chan: chan.clone() };
+    let rx = Rx { chan };
+    (tx, rx)
+}
+
+struct Chan<T> {
+    heap: RefCell<BinaryHeap<Entry<T>>>,
+    /// Incremented on every send, breaks ties between equal priorities.
+    seq: Cell<u64>,
+    lifo: bool,
+    semaphore: Inner,
+    rx_waker: RefCell<Option<Waker>>,
+    tx_count: Cell<usize>,
+}
+
+struct Entry<T> {
+    priority: u32,
+    /// Greater keys are received first among equal priorities.
+    key: u64,
+    value: T,
+}
+
+impl<T> PartialEq for Entry<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<T> Eq for Entry<T> {}
+
+impl<T> PartialOrd for Entry<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T> Ord for Entry<T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.priority
+            .cmp(&other.priority)
+            .then(self.key.cmp(&other.key))
+    }
+}
+
+impl<T> Chan<T> {
+    fn push(&self, priority: u32, value: T) {
+        let seq = self.seq.get();
+        self.seq.set(seq + 1);
+        // the heap pops the greatest key first: invert the sequence number
+        // so that older values win in fifo mode.
+        let key = if self.lifo { seq } else { !seq };
+        self.heap.borrow_mut().push(Entry {
+            priority,
+            key,
+            value,
+        });
+    }
+
+    fn pop(&self) -> Option<T> {
+        let entry = self.heap.borrow_mut().pop()?;
+        self.semaphore.release(1);
+        Some(entry.value)
+    }
+
+    fn close(&self) {
+        self.semaphore.close();
+        self.wake_receiver();
+    }
+
+    fn wake_receiver(&self) {
+        if let Some(w) = self.rx_waker.take() {
+            w.wake();
+        }
+    }
+}
+
+impl<T> Tx<T> {
+    /// Sends a value with the given priority, waiting for a free slot if the
+    /// channel is full. Greater values mean higher priority.
+    pub async fn send(&self, priority: u32, value: T) -> Result<(), SendError<T>> {
+        if self.chan.semaphore.acquire(1).await.is_err() {
+            return Err(SendError::RxClosed(value));
+        }
+        self.chan.push(priority, value);
+        self.chan.wake_receiver();
+        Ok(())
+    }
+
+    pub fn close(&self) {
+        self.chan.close()
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.chan.semaphore.is_closed()
+    }
+
+    pub fn same_channel(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.chan, &other.chan)
+    }
+
+    pub fn hint(&self) -> usize {
+        self.chan.heap.borrow().len()
+    }
+}
+
+impl<T> Clone for Tx<T> {
+    fn clone(&self) -> Self {
+        self.chan.tx_count.set(self.chan.tx_count.get() + 1);
+        Self {
+            chan: self.chan.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Tx<T> {
+    fn drop(&mut self) {
+        let cnt = self.chan.tx_count.get();
+        self.chan.tx_count.set(cnt - 1);
+
+        if cnt == 1 {
+            self.chan.close();
+        }
+    }
+}
+
+impl<T> Rx<T> {
+    /// Receives the value with the highest priority.
+    pub async fn recv(&mut self) -> Option<T> {
+        poll_fn(|cx| self.poll_recv(cx)).await
+    }
+
+    pub fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        match self.try_recv() {
+            Ok(val) => return Poll::Ready(Some(val)),
+            Err(TryRecvError::Empty) => {}
+            Err(_) => return Poll::Ready(None),
+        }
+        let mut borrowed = self.chan.rx_waker.borrow_mut();
+        match borrowed.as_mut() {
+            Some(inner) => {
+                if !inner.will_wake(cx.waker()) {
+                    *inner = cx.waker().clone();
+                }
+            }
+            None => {
+                *borrowed = Some(cx.waker().clone());
+            }
+        }
+        Poll::Pending
+    }
+
+    pub fn try_recv(&mut self) -> Result<T, TryRecvError> {
+        if let Some(val) = self.chan.pop() {
+            return Ok(val);
+        }
+        if self.chan.tx_count.get() == 0 || self.chan.semaphore.is_closed() {
+            Err(TryRecvError::Disconnected)
+        } else {
+            Err(TryRecvError::Empty)
+        }
+    }
+
+    /// Closes the channel without dropping the receiver. Values already
+    /// queued can still be received.
+    pub fn close(&self) {
+        self.chan.close()
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.chan.semaphore.is_closed()
+    }
+
+    pub fn hint(&self) -> usize {
+        self.chan.heap.borrow().len()
+    }
+}
+
+impl<T> Drop for Rx<T> {
+    fn drop(&mut self) {
+        // close the channel, this will make tx send await return.
+        self.chan.close();
+        // consume all elements
+        let heap = std::mem::take(&mut *self.chan.heap.borrow_mut());
+        self.chan.semaphore.release(heap.len());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{channel, lifo_channel};
+    use crate::mpsc::SendError;
+
+    #[monoio::test]
+    async fn test_priority_channel() {
+        let (tx, mut rx) = channel(4);
+        tx.send(0, "bulk 1").await.unwrap();
+        tx.send(0, "bulk 2").await.unwrap();
+        tx.send(5, "control 1").await.unwrap();
+        tx.send(5, "control 2").await.unwrap();
+        assert_eq!(tx.hint(), 4);
+
+        // the channel is full, the sender waits for a free slot
+        let tx2 = tx.clone();
+        let join = monoio::spawn(async move {
+            tx2.send(9, "urgent").await.unwrap();
+        });
+        assert_eq!(rx.recv().await, Some("control 1"));
+        join.await;
+        assert_eq!(rx.recv().await, Some("urgent"));
+        assert_eq!(rx.recv().await, Some("control 2"));
+        assert_eq!(rx.recv().await, Some("bulk 1"));
+        assert_eq!(rx.recv().await, Some("bulk 2"));
+
+        drop(tx);
+        assert_eq!(rx.recv().await, None);
+    }
+
+    #[monoio::test]
+    async fn test_lifo_channel() {
+        let (tx, mut rx) = lifo_channel(8);
+        for i in 0..4 {
+            tx.send(0, i).await.unwrap();
+        }
+        tx.send(1, 10).await.unwrap();
+        assert_eq!(rx.recv().await, Some(10));
+        for i in (0..4).rev() {
+            assert_eq!(rx.recv().await, Some(i));
+        }
+
+        rx.close();
+        assert_eq!(tx.send(0, 5).await, Err(SendError::RxClosed(5)));
+        assert_eq!(rx.recv().await, None);
+    }
+}
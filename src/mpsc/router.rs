@@ -0,0 +1,261 @@
+//! Router which fans values out to per-key unbounded channels.
+//!
+//! Receivers subscribe to a key, and values sent for that key are delivered
+//! to their channel. A route is removed as soon as its [`Route`] is dropped.
+
+use super::{chan::SendError, rx_set::Receiver, unbounded};
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    hash::Hash,
+    ops::{Deref, DerefMut},
+    rc::{Rc, Weak},
+    task::{Context, Poll},
+};
+
+type Routes<K, T> = RefCell<HashMap<K, unbounded::Tx<T>>>;
+
+pub struct Router<K, T> {
+    routes: Rc<Routes<K, T>>,
+    /// Receives the values for keys without a subscriber.
+    default: Option<unbounded::Tx<(K, T)>>,
+}
+
+/// Receiver of the values sent for one key, see [`Router::subscribe`].
+///
+/// Derefs to the underlying channel receiver. Dropping it removes its route.
+pub struct Route<K: Hash + Eq, T> {
+    // dropped first, so the route is closed when `_unregister` runs
+    rx: unbounded::Rx<T>,
+    _unregister: Unregister<K, T>,
+}
+
+struct Unregister<K: Hash + Eq, T> {
+    routes: Weak<Routes<K, T>>,
+    key: K,
+}
+
+impl<K: Hash + Eq, T> Route<K, T> {
+    /// Returns the underlying receiver. The route is then only removed by
+    /// the next send for its key, or by `Router::prune`, once the receiver
+    /// is gone.
+    pub fn into_inner(self) -> unbounded::Rx<T> {
+        let Route { rx, .. } = self;
+        rx
+    }
+}
+
+impl<K: Hash + Eq, T> Deref for Route<K, T> {
+    type Target = unbounded::Rx<T>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.rx
+    }
+}
+
+impl<K: Hash + Eq, T> DerefMut for Route<K, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.rx
+    }
+}
+
+impl<K: Hash + Eq, T> Receiver<T> for Route<K, T> {
+    fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        self.rx.poll_recv(cx)
+    }
+}
+
+impl<K: Hash + Eq, T> Drop for Unregister<K, T> {
+    fn drop(&mut self) {
+        let routes = match self.routes.upgrade() {
+            Some(routes) => routes,
+            None => return,
+        };
+        let mut routes = match routes.try_borrow_mut() {
+            Ok(routes) => routes,
+            // the router is busy, `send` or `prune` removes the route later
+            Err(_) => return,
+        };
+        // the key may have been subscribed to again since
+        let closed = routes
+            .get(&self.key)
+            .map(|tx| tx.is_closed())
+            .unwrap_or(false);
+        if closed {
+            let tx = routes.remove(&self.key);
+            drop(routes);
+            drop(tx);
+        }
+    }
+}
+
+impl<K, T> Router<K, T>
+where
+    K: Hash + Eq,
+{
+    /// Creates a router which rejects values for keys without a subscriber.
+    pub fn new() -> Self {
+        Self {
+            routes: Rc::new(RefCell::new(HashMap::new())),
+            default: None,
+        }
+    }
+
+    /// Creates a router which sends values for keys without a subscriber to
+    /// the returned receiver, together with their key.
+    pub fn with_default() -> (Self, unbounded::Rx<(K, T)>) {
+        let (tx, rx) = unbounded::channel();
+        let router = Self {
+            routes: Rc::new(RefCell::new(HashMap::new())),
+            default: Some(tx),
+        };
+        (router, rx)
+    }
+
+    /// Returns a receiver for the values sent for `key`.
+    ///
+    /// Subscribing to a key again replaces the previous route: the previous
+    /// receiver gets the values already sent and then ends.
+    pub fn subscribe(&self, key: K) -> Route<K, T>
+    where
+        K: Clone,
+    {
+        let (tx, rx) = unbounded::channel();
+        self.routes.borrow_mut().insert(key.clone(), tx);
+        Route {
+            rx,
+            _unregister: Unregister {
+                routes: Rc::downgrade(&self.routes),
+                key,
+            },
+        }
+    }
+
+    /// Removes the route for `key`, its receiver ends once it has received
+    /// the values already sent. Returns `false` if there was no route.
+    pub fn unsubscribe(&self, key: &K) -> bool {
+        self.routes.borrow_mut().remove(key).is_some()
+    }
+
+    /// Sends `value` to the subscriber of `key`, or to the default receiver
+    /// if there is none.
+    ///
+    /// Fails and gives back the key and value if neither is there.
+    pub fn send(&self, key: K, mut value: T) -> Result<(), SendError<(K, T)>> {
+        let mut routes = self.routes.borrow_mut();
+        if let Some(tx) = routes.get(&key) {
+            match tx.send(value) {
+                Ok(()) => return Ok(()),
+                Err(err) => {
+                    // the receiver is gone
+                    routes.remove(&key);
+                    value = err.into_inner();
+                }
+            }
+        }
+        drop(routes);
+
+        match &self.default {
+            Some(tx) => tx
+                .send((key, value))
+                .map_err(|err| SendError::RxClosed(err.into_inner())),
+            None => Err(SendError::RxClosed((key, value))),
+        }
+    }
+
+    /// Returns `true` if there is a route for `key` whose receiver is alive.
+    pub fn contains(&self, key: &K) -> bool {
+        self.routes
+            .borrow()
+            .get(key)
+            .map(|tx| !tx.is_closed())
+            .unwrap_or(false)
+    }
+
+    /// Removes the routes whose receiver has been closed, or dropped after
+    /// `Route::into_inner`.
+    pub fn prune(&self) {
+        self.routes.borrow_mut().retain(|_, tx| !tx.is_closed());
+    }
+
+    /// Returns the number of routes, including the ones whose receiver has
+    /// been closed but which have not been removed yet.
+    pub fn len(&self) -> usize {
+        self.routes.borrow().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.routes.borrow().is_empty()
+    }
+}
+
+impl<K, T> Default for Router<K, T>
+where
+    K: Hash + Eq,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Router;
+    use crate::mpsc::SendError;
+
+    #[monoio::test]
+    async fn test_router() {
+        let router = Router::new();
+        let mut rx1 = router.subscribe(1);
+        let mut rx2 = router.subscribe(2);
+        router.send(1, "a").unwrap();
+        router.send(2, "b").unwrap();
+        router.send(1, "c").unwrap();
+        assert_eq!(router.send(3, "d"), Err(SendError::RxClosed((3, "d"))));
+        assert_eq!(rx1.recv().await, Some("a"));
+        assert_eq!(rx1.recv().await, Some("c"));
+        assert_eq!(rx2.recv().await, Some("b"));
+
+        // the route goes away with its receiver
+        drop(rx2);
+        assert!(!router.contains(&2));
+        assert_eq!(router.len(), 1);
+        assert!(router.send(2, "e").is_err());
+
+        // a closed route is removed on the next send for its key
+        let rx3 = router.subscribe(3).into_inner();
+        rx3.close();
+        assert_eq!(router.len(), 2);
+        assert!(router.send(3, "f").is_err());
+        assert_eq!(router.len(), 1);
+
+        // dropping a replaced receiver keeps the new route
+        let new = router.subscribe(1);
+        assert_eq!(rx1.recv().await, None);
+        drop(rx1);
+        assert!(router.contains(&1));
+        let mut rx1 = new;
+
+        assert!(router.unsubscribe(&1));
+        assert_eq!(rx1.recv().await, None);
+        assert!(router.is_empty());
+    }
+
+    #[monoio::test]
+    async fn test_router_default() {
+        let (router, mut unmatched) = Router::with_default();
+        let mut rx = router.subscribe("a");
+        router.send("a", 1).unwrap();
+        router.send("b", 2).unwrap();
+        assert_eq!(rx.recv().await, Some(1));
+        rx.close();
+        router.prune();
+        assert!(router.is_empty());
+        router.send("a", 3).unwrap();
+        assert_eq!(unmatched.recv().await, Some(("b", 2)));
+        assert_eq!(unmatched.recv().await, Some(("a", 3)));
+
+        drop(router);
+        assert_eq!(unmatched.recv().await, None);
+    }
+}
@@ -0,0 +1,337 @@
+//! A set of receivers which can be received from as one.
+//!
+//! Every receiver in the set is polled with its own waker, which queues the
+//! receiver and wakes the task owning the set. Only queued receivers are
+//! polled again, and they are served in turn so that a busy receiver can not
+//! starve the others. Receivers are removed once they are closed and empty.
+
+use super::{array, bounded, fair, priority, unbounded};
+use futures_lite::future::poll_fn;
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    task::{Context, Poll, Wake, Waker},
+};
+
+/// A receiver which can be put into a [`RxSet`].
+pub trait Receiver<T> {
+    fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<Option<T>>;
+}
+
+impl<T, E> Receiver<T> for bounded::Rx<T, E> {
+    fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        bounded::Rx::poll_recv(self, cx)
+    }
+}
+
+impl<T, E> Receiver<T> for unbounded::Rx<T, E> {
+    fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        unbounded::Rx::poll_recv(self, cx)
+    }
+}
+
+impl<T, const N: usize> Receiver<T> for array::Rx<T, N> {
+    fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        array::Rx::poll_recv(self, cx)
+    }
+}
+
+impl<T> Receiver<T> for priority::Rx<T> {
+    fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        priority::Rx::poll_recv(self, cx)
+    }
+}
+
+impl<T> Receiver<T> for fair::Rx<T> {
+    fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        fair::Rx::poll_recv(self, cx)
+    }
+}
+
+pub struct RxSet<K, T> {
+    entries: Vec<Option<Entry<K, T>>>,
+    /// Indexes of free entries.
+    free: Vec<usize>,
+    len: usize,
+    /// Counter for entry generations.
+    generation: u64,
+    shared: Arc<Shared>,
+}
+
+struct Entry<K, T> {
+    key: K,
+    rx: Box<dyn Receiver<T>>,
+    waker: Arc<EntryWaker>,
+}
+
+struct Shared {
+    /// Indexes and generations of the entries to poll, in the order they are
+    /// served. Items of removed entries are skipped, also once their index
+    /// has been reused.
+    ready: Mutex<VecDeque<(usize, u64)>>,
+    /// The waker of the task receiving from the set.
+    waker: Mutex<Option<Waker>>,
+}
+
+/// Thread-safe, as the receivers may hand their waker to other threads.
+struct EntryWaker {
+    index: usize,
+    generation: u64,
+    /// Whether the entry is in the ready queue.
+    queued: AtomicBool,
+    shared: Arc<Shared>,
+}
+
+impl EntryWaker {
+    /// Queues the entry unless it is queued already. Returns `false` if it
+    /// was.
+    fn queue(&self) -> bool {
+        if self.queued.swap(true, Ordering::AcqRel) {
+            return false;
+        }
+        self.shared
+            .ready
+            .lock()
+            .unwrap()
+            .push_back((self.index, self.generation));
+        true
+    }
+}
+
+impl Wake for EntryWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref()
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        if !self.queue() {
+            return;
+        }
+        let waker = self.shared.waker.lock().unwrap().clone();
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+impl<K, T> RxSet<K, T>
+where
+    K: Clone + PartialEq,
+{
+    pub fn new() -> Self {
+        Self {
+            entries: Vec::new(),
+            free: Vec::new(),
+            len: 0,
+            generation: 0,
+            shared: Arc::new(Shared {
+                ready: Mutex::new(VecDeque::new()),
+                waker: Mutex::new(None),
+            }),
+        }
+    }
+
+    /// Inserts a receiver with the given key. A receiver with the same key is
+    /// replaced and dropped.
+    pub fn insert<R>(&mut self, key: K, rx: R)
+    where
+        R: Receiver<T> + 'static,
+    {
+        self.remove(&key);
+        let index = self.free.pop().unwrap_or(self.entries.len());
+        self.generation += 1;
+        let waker = Arc::new(EntryWaker {
+            index,
+            generation: self.generation,
+            queued: AtomicBool::new(false),
+            shared: self.shared.clone(),
+        });
+        // poll the new receiver once to register its waker
+        waker.queue();
+        let entry = Entry {
+            key,
+            rx: Box::new(rx),
+            waker,
+        };
+        if index == self.entries.len() {
+            self.entries.push(Some(entry));
+        } else {
+            self.entries[index] = Some(entry);
+        }
+        self.len += 1;
+    }
+
+    /// Removes the receiver with the given key. Returns `false` if there was
+    /// none.
+    pub fn remove(&mut self, key: &K) -> bool {
+        let found = self
+            .entries
+            .iter()
+            .position(|entry| entry.as_ref().map(|e| &e.key == key).unwrap_or(false));
+        match found {
+            Some(index) => {
+                self.remove_at(index);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn remove_at(&mut self, index: usize) {
+        self.entries[index] = None;
+        self.free.push(index);
+        self.len -= 1;
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.entries.iter().flatten().any(|entry| &entry.key == key)
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Receives a value from any receiver in the set, with the key of that
+    /// receiver.
+    ///
+    /// Returns `None` once the set is empty: all receivers have been removed
+    /// or were closed and drained.
+    pub async fn recv(&mut self) -> Option<(K, T)> {
+        poll_fn(|cx| self.poll_recv(cx)).await
+    }
+
+    pub fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<Option<(K, T)>> {
+        {
+            let mut waker = self.shared.waker.lock().unwrap();
+            if !waker
+                .as_ref()
+                .map(|w| w.will_wake(cx.waker()))
+                .unwrap_or(false)
+            {
+                *waker = Some(cx.waker().clone());
+            }
+        }
+
+        loop {
+            let next = self.shared.ready.lock().unwrap().pop_front();
+            let (index, generation) = match next {
+                Some(next) => next,
+                None => break,
+            };
+            let entry = match self.entries.get_mut(index) {
+                Some(Some(entry)) if entry.waker.generation == generation => entry,
+                _ => continue,
+            };
+            entry.waker.queued.store(false, Ordering::Release);
+            let waker = Waker::from(entry.waker.clone());
+            let mut entry_cx = Context::from_waker(&waker);
+            match entry.rx.poll_recv(&mut entry_cx) {
+                Poll::Ready(Some(val)) => {
+                    // there may be more, poll it again after the others
+                    entry.waker.queue();
+                    return Poll::Ready(Some((entry.key.clone(), val)));
+                }
+                Poll::Ready(None) => self.remove_at(index),
+                Poll::Pending => {}
+            }
+        }
+
+        if self.is_empty() {
+            Poll::Ready(None)
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+impl<K, T> Default for RxSet<K, T>
+where
+    K: Clone + PartialEq,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Receiver, RxSet};
+    use crate::mpsc::{bounded, unbounded};
+    use std::{
+        cell::Cell,
+        rc::Rc,
+        task::{Context, Poll},
+    };
+
+    struct Counted<R>(R, Rc<Cell<usize>>);
+
+    impl<T, R: Receiver<T>> Receiver<T> for Counted<R> {
+        fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<Option<T>> {
+            self.1.set(self.1.get() + 1);
+            self.0.poll_recv(cx)
+        }
+    }
+
+    #[monoio::test]
+    async fn test_rx_set() {
+        let (tx1, rx1) = unbounded::channel();
+        let (tx2, rx2) = bounded::channel(8);
+        let polls = Rc::new(Cell::new(0));
+        let mut set = RxSet::new();
+        set.insert("a", rx1);
+        set.insert("b", Counted(rx2, polls.clone()));
+
+        for i in 0..3 {
+            tx1.send(i).unwrap();
+        }
+        tx2.send(10).await.unwrap();
+        // served in turn
+        assert_eq!(set.recv().await, Some(("a", 0)));
+        assert_eq!(set.recv().await, Some(("b", 10)));
+        assert_eq!(set.recv().await, Some(("a", 1)));
+        assert_eq!(set.recv().await, Some(("a", 2)));
+        let polls_before = polls.get();
+
+        // "b" is not woken, so it is not polled again
+        let join = monoio::spawn(async move {
+            for i in 3..6 {
+                tx1.send(i).unwrap();
+            }
+        });
+        for i in 3..6 {
+            assert_eq!(set.recv().await, Some(("a", i)));
+        }
+        join.await;
+        assert_eq!(polls.get(), polls_before);
+
+        // closed receivers leave the set
+        assert_eq!(set.len(), 2);
+        drop(tx2);
+        assert_eq!(set.recv().await, None);
+        assert!(set.is_empty());
+    }
+
+    #[monoio::test]
+    async fn test_rx_set_reused_index() {
+        let (tx1, rx1) = unbounded::channel::<i32>();
+        let mut set = RxSet::new();
+        set.insert("a", rx1);
+        // "a" is still queued to be polled once
+        assert!(set.remove(&"a"));
+
+        let (_tx2, rx2) = unbounded::channel::<i32>();
+        let polls = Rc::new(Cell::new(0));
+        set.insert("b", Counted(rx2, polls.clone()));
+        // the stale item of "a" does not poll "b", which reuses its index
+        assert!(futures_lite::future::poll_once(set.recv()).await.is_none());
+        assert_eq!(polls.get(), 1);
+        drop(tx1);
+    }
+}
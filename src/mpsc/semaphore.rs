@@ -1,4 +1,9 @@
-use std::cell::UnsafeCell;
+use std::{
+    cell::{Cell, UnsafeCell},
+    rc::Rc,
+};
+
+use crate::semaphore::{Acquire, Inner};
 
 pub trait Semaphore {
     fn add_permits(&self, n: usize);
@@ -45,3 +50,60 @@ impl Semaphore for Unlimited {
         unsafe { *self.closed.get() }
     }
 }
+
+/// Capacity of a bounded channel, either its own or drawn from a semaphore
+/// shared with other channels.
+pub enum Bounded {
+    Owned(Inner),
+    Shared {
+        semaphore: Rc<crate::semaphore::Semaphore>,
+        /// Closing the channel must not close the shared semaphore.
+        closed: Cell<bool>,
+    },
+}
+
+impl Bounded {
+    pub fn shared(semaphore: Rc<crate::semaphore::Semaphore>) -> Self {
+        Bounded::Shared {
+            semaphore,
+            closed: Cell::new(false),
+        }
+    }
+
+    /// Returns `true` if the permits come from a semaphore shared with other
+    /// channels, which is not closed with this channel.
+    pub fn is_shared(&self) -> bool {
+        matches!(self, Bounded::Shared { .. })
+    }
+
+    fn inner(&self) -> &Inner {
+        match self {
+            Bounded::Owned(inner) => inner,
+            Bounded::Shared { semaphore, .. } => semaphore.inner(),
+        }
+    }
+
+    pub fn acquire(&self, n: u32) -> Acquire<'_> {
+        self.inner().acquire(n)
+    }
+}
+
+impl Semaphore for Bounded {
+    fn add_permits(&self, n: usize) {
+        self.inner().release(n);
+    }
+
+    fn close(&self) {
+        match self {
+            Bounded::Owned(inner) => inner.close(),
+            Bounded::Shared { closed, .. } => closed.set(true),
+        }
+    }
+
+    fn is_closed(&self) -> bool {
+        match self {
+            Bounded::Owned(inner) => inner.is_closed(),
+            Bounded::Shared { semaphore, closed } => closed.get() || semaphore.is_closed(),
+        }
+    }
+}
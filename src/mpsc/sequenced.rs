@@ -0,0 +1,348 @@
+//! Bounded channel which delivers values in sequence order.
+//!
+//! Senders number their values, starting from 0, and may send them in any
+//! order. The receiver gets them strictly in sequence order, buffering the
+//! values which arrive early.
+//!
+//! Buffered values take a permit from a semaphore, so while a sequence number
+//! is missing at most `window` later values are buffered and senders of
+//! further values wait. The value the receiver is waiting for never needs a
+//! permit: it is delivered right away, so a full buffer can not block it.
+
+use super::chan::{SendError, TryRecvError};
+use crate::semaphore::Inner;
+use futures_lite::future::poll_fn;
+use std::{
+    cell::{Cell, RefCell},
+    collections::BTreeMap,
+    future::Future,
+    pin::pin,
+    rc::Rc,
+    task::{Context, Poll, Waker},
+};
+
+pub struct Tx<T> {
+    chan: Rc<Chan<T>>,
+}
+
+pub struct Rx<T> {
+    chan: Rc<Chan<T>>,
+}
+
+pub fn channel<T>(window: usize) -> (Tx<T>, Rx<T>) {
+    let chan = Rc::new(Chan {
+        values: RefCell::new(BTreeMap::new()),
+        next: Cell::new(0),
+        semaphore: Inner::new(window),
+        waiters: RefCell::new(BTreeMap::new()),
+        rx_waker: RefCell::new(None),
+        tx_count: Cell::new(1),
+    });
+    let tx = Tx { chan: chan.clone() };
+    let rx = Rx { chan };
+    (tx, rx)
+}
+
+struct Chan<T> {
+    /// Sent values by sequence number, with whether they hold a permit.
+    values: RefCell<BTreeMap<u64, (T, bool)>>,
+    /// The sequence number the receiver is waiting for.
+    next: Cell<u64>,
+    semaphore: Inner,
+    /// Wakers of the senders waiting for `next` to reach their sequence
+    /// number, by sequence number. Only the sender of the new `next` is woken
+    /// when the receiver advances.
+    waiters: RefCell<BTreeMap<u64, Waker>>,
+    rx_waker: RefCell<Option<Waker>>,
+    tx_count: Cell<usize>,
+}
+
+impl<T> Chan<T> {
+    fn close(&self) {
+        self.semaphore.close();
+        self.wake_receiver();
+    }
+
+    fn wake_receiver(&self) {
+        if let Some(w) = self.rx_waker.take() {
+            w.wake();
+        }
+    }
+
+    fn register(&self, seq: u64, waker: &Waker) {
+        let mut waiters = self.waiters.borrow_mut();
+        match waiters.get_mut(&seq) {
+            Some(inner) => {
+                if !inner.will_wake(waker) {
+                    *inner = waker.clone();
+                }
+            }
+            None => {
+                waiters.insert(seq, waker.clone());
+            }
+        }
+    }
+
+    fn check_seq(&self, seq: u64) {
+        assert!(
+            seq >= self.next.get() && !self.values.borrow().contains_key(&seq),
+            "sequence number {} has already been sent",
+            seq
+        );
+    }
+}
+
+impl<T> Tx<T> {
+    /// Sends the value with sequence number `seq`. Waits while the receiver
+    /// is missing an earlier value and the buffer is full.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a value with sequence number `seq` has already been sent.
+    pub async fn send(&self, seq: u64, value: T) -> Result<(), SendError<T>> {
+        let chan = &self.chan;
+        chan.check_seq(seq);
+
+        let mut acquire = pin!(chan.semaphore.acquire(1));
+        let waiting = Waiting { chan, seq };
+        let permit = poll_fn(|cx| {
+            if seq == chan.next.get() {
+                return Poll::Ready(Ok(false));
+            }
+            if let Poll::Ready(res) = acquire.as_mut().poll(cx) {
+                return Poll::Ready(res.map(|_| true));
+            }
+            chan.register(seq, cx.waker());
+            Poll::Pending
+        })
+        .await;
+        drop(waiting);
+        let permit = match permit {
+            Ok(permit) => permit,
+            Err(_) => return Err(SendError::RxClosed(value)),
+        };
+
+        if chan.semaphore.is_closed() {
+            if permit {
+                chan.semaphore.release(1);
+            }
+            return Err(SendError::RxClosed(value));
+        }
+        chan.check_seq(seq);
+        chan.values.borrow_mut().insert(seq, (value, permit));
+        if seq == chan.next.get() {
+            chan.wake_receiver();
+        }
+        Ok(())
+    }
+
+    pub fn close(&self) {
+        self.chan.close()
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.chan.semaphore.is_closed()
+    }
+
+    pub fn same_channel(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.chan, &other.chan)
+    }
+
+    pub fn hint(&self) -> usize {
+        self.chan.values.borrow().len()
+    }
+}
+
+/// Removes the waker of a sender from `Chan::waiters` once it stops waiting,
+/// also when the send future is dropped.
+struct Waiting<'a, T> {
+    chan: &'a Chan<T>,
+    seq: u64,
+}
+
+impl<T> Drop for Waiting<'_, T> {
+    fn drop(&mut self) {
+        self.chan.waiters.borrow_mut().remove(&self.seq);
+    }
+}
+
+impl<T> Clone for Tx<T> {
+    fn clone(&self) -> Self {
+        self.chan.tx_count.set(self.chan.tx_count.get() + 1);
+        Self {
+            chan: self.chan.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Tx<T> {
+    fn drop(&mut self) {
+        let cnt = self.chan.tx_count.get();
+        self.chan.tx_count.set(cnt - 1);
+
+        if cnt == 1 {
+            self.chan.close();
+        }
+    }
+}
+
+impl<T> Rx<T> {
+    /// Receives the next value in sequence order with its sequence number.
+    ///
+    /// Returns `None` once the channel is closed and the next value has not
+    /// been sent, values buffered after the gap are dropped with the channel.
+    pub async fn recv(&mut self) -> Option<(u64, T)> {
+        poll_fn(|cx| self.poll_recv(cx)).await
+    }
+
+    pub fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<Option<(u64, T)>> {
+        match self.try_recv() {
+            Ok(val) => return Poll::Ready(Some(val)),
+            Err(TryRecvError::Empty) => {}
+            Err(_) => return Poll::Ready(None),
+        }
+        let mut borrowed = self.chan.rx_waker.borrow_mut();
+        match borrowed.as_mut() {
+            Some(inner) => {
+                if !inner.will_wake(cx.waker()) {
+                    *inner = cx.waker().clone();
+                }
+            }
+            None => {
+                *borrowed = Some(cx.waker().clone());
+            }
+        }
+        Poll::Pending
+    }
+
+    pub fn try_recv(&mut self) -> Result<(u64, T), TryRecvError> {
+        let seq = self.chan.next.get();
+        let entry = self.chan.values.borrow_mut().remove(&seq);
+        if let Some((val, permit)) = entry {
+            self.chan.next.set(seq + 1);
+            if permit {
+                self.chan.semaphore.release(1);
+            }
+            let waiter = self.chan.waiters.borrow_mut().remove(&(seq + 1));
+            if let Some(waker) = waiter {
+                waker.wake();
+            }
+            return Ok((seq, val));
+        }
+        if self.chan.tx_count.get() == 0 || self.chan.semaphore.is_closed() {
+            Err(TryRecvError::Disconnected)
+        } else {
+            Err(TryRecvError::Empty)
+        }
+    }
+
+    /// Returns the sequence number of the next value to be received.
+    pub fn next_seq(&self) -> u64 {
+        self.chan.next.get()
+    }
+
+    /// Closes the channel without dropping the receiver. Values already
+    /// buffered can still be received up to the first gap.
+    pub fn close(&self) {
+        self.chan.close()
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.chan.semaphore.is_closed()
+    }
+
+    pub fn hint(&self) -> usize {
+        self.chan.values.borrow().len()
+    }
+}
+
+impl<T> Drop for Rx<T> {
+    fn drop(&mut self) {
+        // close the channel, this will make tx send await return.
+        self.chan.close();
+        // consume all elements
+        let values = std::mem::take(&mut *self.chan.values.borrow_mut());
+        let permits = values.values().filter(|(_, permit)| *permit).count();
+        self.chan.semaphore.release(permits);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::channel;
+    use futures_lite::future::yield_now;
+
+    #[monoio::test]
+    async fn test_sequenced_channel() {
+        let (tx, mut rx) = channel(2);
+        tx.send(2, "c").await.unwrap();
+        tx.send(1, "b").await.unwrap();
+        assert!(rx.try_recv().is_err());
+
+        // the buffer is full, only the missing value gets through
+        let tx2 = tx.clone();
+        let join = monoio::spawn(async move {
+            tx2.send(3, "d").await.unwrap();
+        });
+        yield_now().await;
+        assert_eq!(rx.hint(), 2);
+        tx.send(0, "a").await.unwrap();
+        for (seq, val) in ["a", "b", "c", "d"].into_iter().enumerate() {
+            assert_eq!(rx.recv().await, Some((seq as u64, val)));
+        }
+        join.await;
+        assert_eq!(rx.next_seq(), 4);
+
+        // a gap is never filled once the senders are gone
+        tx.send(5, "f").await.unwrap();
+        drop(tx);
+        assert_eq!(rx.recv().await, None);
+    }
+
+    #[monoio::test]
+    async fn test_sequenced_channel_waiting_sender_becomes_next() {
+        let (tx, mut rx) = channel(1);
+        tx.send(2, 2).await.unwrap();
+        let tx2 = tx.clone();
+        // waits for a permit, until the receiver is waiting for it
+        let join = monoio::spawn(async move {
+            tx2.send(1, 1).await.unwrap();
+        });
+        yield_now().await;
+        tx.send(0, 0).await.unwrap();
+        for seq in 0..3 {
+            assert_eq!(rx.recv().await, Some((seq, seq)));
+        }
+        join.await;
+    }
+
+    #[monoio::test]
+    async fn test_sequenced_channel_wakes_next_sender_only() {
+        let (tx, mut rx) = channel(1);
+        tx.send(3, 3).await.unwrap();
+        let mut joins = Vec::new();
+        for seq in 1..3 {
+            let tx = tx.clone();
+            joins.push(monoio::spawn(async move {
+                tx.send(seq, seq).await.unwrap();
+            }));
+        }
+        yield_now().await;
+        assert_eq!(rx.chan.waiters.borrow().len(), 2);
+
+        tx.send(0, 0).await.unwrap();
+        assert_eq!(rx.recv().await, Some((0, 0)));
+        // only the sender of 1 is woken, the sender of 2 keeps waiting
+        assert_eq!(
+            rx.chan.waiters.borrow().keys().copied().collect::<Vec<_>>(),
+            [2]
+        );
+        for seq in 1..4 {
+            assert_eq!(rx.recv().await, Some((seq, seq)));
+        }
+        for join in joins {
+            join.await;
+        }
+        assert!(rx.chan.waiters.borrow().is_empty());
+    }
+}
@@ -0,0 +1,329 @@
+//! Duplicates a receiver into many receivers which each get every value.
+//!
+//! There is no task moving values from the source: a tee receiver which runs
+//! out of values receives from the source itself and queues a clone of the
+//! value for each of the other tee receivers. Each tee receiver queues at most
+//! `capacity` values, what happens when a slow receiver's queue is full is
+//! decided by the [`Policy`].
+
+use super::rx_set::Receiver;
+use futures_lite::future::poll_fn;
+use std::{
+    cell::{Cell, RefCell},
+    collections::VecDeque,
+    rc::Rc,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Wake, Waker},
+};
+
+/// What to do with a value for a tee receiver whose queue is full.
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Default)]
+pub enum Policy {
+    /// Stop receiving from the source until the slow receiver catches up, so
+    /// the senders of a bounded source wait.
+    #[default]
+    Block,
+    /// Drop the value for the slow receiver and count it, see
+    /// [`Rx::dropped`].
+    Drop,
+}
+
+pub struct Rx<T> {
+    shared: Rc<Shared<T>>,
+    index: usize,
+}
+
+/// Splits `rx` into `n` receivers which each get a clone of every value.
+///
+/// # Panics
+///
+/// Panics if `n` or `capacity` is 0.
+pub fn tee<T, R>(rx: R, n: usize, capacity: usize, policy: Policy) -> Vec<Rx<T>>
+where
+    T: Clone + 'static,
+    R: Receiver<T> + 'static,
+{
+    assert!(n > 0, "tee must have at least one receiver");
+    assert!(capacity > 0, "tee capacity must be greater than 0");
+    let shared = Rc::new(Shared {
+        source: RefCell::new(Box::new(rx)),
+        source_waker: Arc::new(SourceWaker {
+            wakers: Mutex::new(Vec::new()),
+        }),
+        done: Cell::new(false),
+        outputs: RefCell::new(
+            (0..n)
+                .map(|_| Output {
+                    queue: VecDeque::new(),
+                    waker: None,
+                    dropped: 0,
+                    alive: true,
+                })
+                .collect(),
+        ),
+        capacity,
+        policy,
+    });
+    (0..n)
+        .map(|index| Rx {
+            shared: shared.clone(),
+            index,
+        })
+        .collect()
+}
+
+struct Shared<T> {
+    source: RefCell<Box<dyn Receiver<T>>>,
+    /// Wakes every tee receiver waiting for the source.
+    source_waker: Arc<SourceWaker>,
+    /// Set once the source has ended.
+    done: Cell<bool>,
+    outputs: RefCell<Vec<Output<T>>>,
+    capacity: usize,
+    policy: Policy,
+}
+
+struct Output<T> {
+    queue: VecDeque<T>,
+    waker: Option<Waker>,
+    dropped: usize,
+    alive: bool,
+}
+
+/// Thread-safe, as the source may hand its waker to other threads.
+struct SourceWaker {
+    wakers: Mutex<Vec<Waker>>,
+}
+
+impl SourceWaker {
+    fn register(&self, waker: &Waker) {
+        let mut wakers = self.wakers.lock().unwrap();
+        if !wakers.iter().any(|w| w.will_wake(waker)) {
+            wakers.push(waker.clone());
+        }
+    }
+}
+
+impl Wake for SourceWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref()
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        let wakers = std::mem::take(&mut *self.wakers.lock().unwrap());
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+}
+
+/// Wakers taken from the outputs, woken once `Shared::outputs` is no longer
+/// borrowed.
+struct Wakers(Vec<Waker>);
+
+impl Wakers {
+    fn take_all<T>(outputs: &mut [Output<T>]) -> Self {
+        Wakers(
+            outputs
+                .iter_mut()
+                .filter_map(|output| output.waker.take())
+                .collect(),
+        )
+    }
+
+    fn wake(self) {
+        for waker in self.0 {
+            waker.wake();
+        }
+    }
+}
+
+impl<T: Clone> Rx<T> {
+    pub async fn recv(&mut self) -> Option<T> {
+        poll_fn(|cx| self.poll_recv(cx)).await
+    }
+
+    pub fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        let shared = &*self.shared;
+        let mut outputs = shared.outputs.borrow_mut();
+        if let Some(val) = outputs[self.index].queue.pop_front() {
+            if shared.policy == Policy::Block
+                && outputs[self.index].queue.len() + 1 == shared.capacity
+            {
+                // the source may have been blocked on this receiver
+                let wakers = Wakers::take_all(&mut outputs);
+                drop(outputs);
+                wakers.wake();
+            }
+            return Poll::Ready(Some(val));
+        }
+        if shared.done.get() {
+            return Poll::Ready(None);
+        }
+        outputs[self.index].waker = Some(cx.waker().clone());
+        if shared.policy == Policy::Block
+            && outputs
+                .iter()
+                .any(|output| output.alive && output.queue.len() >= shared.capacity)
+        {
+            return Poll::Pending;
+        }
+        // the source may call back into this tee
+        drop(outputs);
+
+        shared.source_waker.register(cx.waker());
+        let waker = Waker::from(shared.source_waker.clone());
+        let polled = shared
+            .source
+            .borrow_mut()
+            .poll_recv(&mut Context::from_waker(&waker));
+        let mut outputs = shared.outputs.borrow_mut();
+        match polled {
+            Poll::Ready(Some(val)) => {
+                outputs[self.index].waker = None;
+                let mut wakers = Wakers(Vec::new());
+                for (index, output) in outputs.iter_mut().enumerate() {
+                    if index == self.index || !output.alive {
+                        continue;
+                    }
+                    if output.queue.len() < shared.capacity {
+                        output.queue.push_back(val.clone());
+                        wakers.0.extend(output.waker.take());
+                    } else {
+                        output.dropped += 1;
+                    }
+                }
+                drop(outputs);
+                wakers.wake();
+                Poll::Ready(Some(val))
+            }
+            Poll::Ready(None) => {
+                shared.done.set(true);
+                let wakers = Wakers::take_all(&mut outputs);
+                drop(outputs);
+                wakers.wake();
+                Poll::Ready(None)
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+
+    /// Returns the number of values this receiver missed because its queue
+    /// was full, see [`Policy::Drop`].
+    pub fn dropped(&self) -> usize {
+        self.shared.outputs.borrow()[self.index].dropped
+    }
+
+    pub fn hint(&self) -> usize {
+        self.shared.outputs.borrow()[self.index].queue.len()
+    }
+}
+
+impl<T: Clone> Receiver<T> for Rx<T> {
+    fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        Rx::poll_recv(self, cx)
+    }
+}
+
+impl<T> Drop for Rx<T> {
+    fn drop(&mut self) {
+        let mut outputs = self.shared.outputs.borrow_mut();
+        let output = &mut outputs[self.index];
+        output.alive = false;
+        let queue = std::mem::take(&mut output.queue);
+        // the others may have been blocked on this receiver
+        let wakers = Wakers::take_all(&mut outputs);
+        drop(outputs);
+        drop(queue);
+        wakers.wake();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{tee, Policy, Rx};
+    use crate::mpsc::{bounded, rx_set::Receiver, unbounded};
+    use futures_lite::future::poll_once;
+    use std::{
+        cell::RefCell,
+        rc::Rc,
+        task::{Context, Poll},
+    };
+
+    /// Looks at another tee receiver whenever the source is polled.
+    struct Probe<R>(R, Rc<RefCell<Option<Rx<i32>>>>);
+
+    impl<R: Receiver<i32>> Receiver<i32> for Probe<R> {
+        fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<Option<i32>> {
+            if let Some(rx) = &*self.1.borrow() {
+                rx.hint();
+            }
+            self.0.poll_recv(cx)
+        }
+    }
+
+    #[monoio::test]
+    async fn test_tee_block() {
+        let (tx, rx) = bounded::channel(1);
+        let mut rxs = rx.tee(2, 2, Policy::Block);
+        let mut slow = rxs.pop().unwrap();
+        let mut fast = rxs.pop().unwrap();
+        let join = monoio::spawn(async move {
+            for i in 0..5 {
+                tx.send(i).await.unwrap();
+            }
+        });
+        assert_eq!(fast.recv().await, Some(0));
+        assert_eq!(fast.recv().await, Some(1));
+        // the slow receiver has 2 values queued, the source is not received
+        // from until it catches up
+        assert!(poll_once(fast.recv()).await.is_none());
+        assert_eq!(slow.recv().await, Some(0));
+        assert_eq!(fast.recv().await, Some(2));
+        for i in 1..5 {
+            assert_eq!(slow.recv().await, Some(i));
+        }
+        join.await;
+        for i in 3..5 {
+            assert_eq!(fast.recv().await, Some(i));
+        }
+        assert_eq!(fast.recv().await, None);
+        assert_eq!(slow.recv().await, None);
+        assert_eq!(fast.dropped() + slow.dropped(), 0);
+    }
+
+    #[monoio::test]
+    async fn test_tee_drop() {
+        let (tx, rx) = unbounded::channel();
+        let mut rxs = rx.tee(3, 1, Policy::Drop);
+        for i in 0..3 {
+            tx.send(i).unwrap();
+        }
+        drop(tx);
+        let gone = rxs.pop().unwrap();
+        drop(gone);
+        let mut slow = rxs.pop().unwrap();
+        let mut fast = rxs.pop().unwrap();
+        for i in 0..3 {
+            assert_eq!(fast.recv().await, Some(i));
+        }
+        assert_eq!(fast.recv().await, None);
+        assert_eq!(slow.recv().await, Some(0));
+        assert_eq!(slow.recv().await, None);
+        assert_eq!(slow.dropped(), 2);
+        assert_eq!(fast.dropped(), 0);
+    }
+
+    #[monoio::test]
+    async fn test_tee_reentrant_source() {
+        let (tx, rx) = unbounded::channel();
+        let other = Rc::new(RefCell::new(None));
+        let mut rxs = tee(Probe(rx, other.clone()), 2, 1, Policy::Drop);
+        *other.borrow_mut() = rxs.pop();
+        let mut first = rxs.pop().unwrap();
+        tx.send(1).unwrap();
+        assert_eq!(first.recv().await, Some(1));
+        assert_eq!(other.borrow().as_ref().unwrap().hint(), 1);
+        other.borrow_mut().take();
+    }
+}
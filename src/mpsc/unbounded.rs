@@ -1,13 +1,20 @@
 use super::{
-    chan::{self, SendError, TryRecvError},
+    chan::{self, PanicPolicy, RecvError, SendError, TryRecvError},
     semaphore::Unlimited,
+    tee,
 };
 use futures_lite::future::poll_fn;
-use std::task::{Context, Poll};
+use std::{
+    cell::Ref,
+    task::{Context, Poll},
+};
+
+pub struct Tx<T, E = ()>(chan::Tx<T, Unlimited, E>);
 
-pub struct Tx<T>(chan::Tx<T, Unlimited>);
+pub struct Rx<T, E = ()>(chan::Rx<T, Unlimited, E>);
 
-pub struct Rx<T>(chan::Rx<T, Unlimited>);
+/// Receiver which yields values in batches, see [`Rx::batched`].
+pub type Batched<T, E = ()> = chan::Batched<Rx<T, E>>;
 
 pub fn channel<T>() -> (Tx<T>, Rx<T>) {
     let semaphore = Unlimited::new();
@@ -15,19 +22,100 @@ pub fn channel<T>() -> (Tx<T>, Rx<T>) {
     (Tx(tx), Rx(rx))
 }
 
-impl<T> Tx<T> {
-    pub fn send(&self, value: T) -> Result<(), SendError> {
+/// Creates a channel which gives senders a soft back-pressure signal.
+///
+/// The channel is over its high watermark once more than `high` values are
+/// queued, and stays so until the receiver has drained it down to `low`
+/// values. Sending never blocks, producers are expected to check
+/// [`Tx::is_over_high_watermark`] or wait with
+/// [`Tx::wait_below_low_watermark`]. A task watching the channel, e.g. to
+/// report overload, can wait with [`Tx::wait_over_high_watermark`].
+///
+/// # Panics
+///
+/// Panics if `low` is greater than `high`.
+pub fn channel_with_watermarks<T>(low: usize, high: usize) -> (Tx<T>, Rx<T>) {
+    assert!(low <= high, "low watermark must not exceed high watermark");
+    let semaphore = Unlimited::new();
+    let (tx, rx) = chan::channel_with_watermarks(semaphore, low, high);
+    (Tx(tx), Rx(rx))
+}
+
+/// Creates a channel whose senders can close it with a reason of type `E`,
+/// see [`Tx::close_with`].
+pub fn channel_with_reason<T, E>() -> (Tx<T, E>, Rx<T, E>) {
+    let semaphore = Unlimited::new();
+    let (tx, rx) = chan::channel_with_reason(semaphore);
+    (Tx(tx), Rx(rx))
+}
+
+impl<T, E> Tx<T, E> {
+    pub fn send(&self, value: T) -> Result<(), SendError<T>> {
         self.0.send(value)
     }
 
+    /// Closes the channel: later sends fail and give the value back, and the
+    /// receiver ends once it has received all queued values.
     pub fn close(&self) {
         self.0.close()
     }
 
+    /// Closes the channel for all senders, e.g. because the producer failed.
+    ///
+    /// Values sent before are still received, after which the receiver sees
+    /// [`RecvError::Closed`] with this reason from [`Rx::recv_result`], or
+    /// [`TryRecvError::Closed`] from [`Rx::try_recv`]. The reason is moved out
+    /// to the first of them, and only the first reason is kept.
+    pub fn close_with(&self, reason: E) {
+        self.0.close_with(reason)
+    }
+
     pub fn is_closed(&self) -> bool {
         self.0.is_closed()
     }
 
+    /// Completes when the receiver has been dropped or the channel has been
+    /// closed.
+    ///
+    /// This is useful to stop producing values nobody will receive.
+    pub async fn closed(&self) {
+        self.0.closed().await
+    }
+
+    /// Checks whether the channel has been closed, and if not, schedules the
+    /// `Waker` in the provided `Context` to be notified when it is.
+    ///
+    /// Only the `Waker` from the most recent call is notified.
+    pub fn poll_closed(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        self.0.poll_closed(cx)
+    }
+
+    /// Returns `true` if the channel has gone over its high watermark and
+    /// has not been drained to its low watermark since. Always `false` for a
+    /// channel not created with [`channel_with_watermarks`].
+    pub fn is_over_high_watermark(&self) -> bool {
+        self.0.is_over_high_watermark()
+    }
+
+    /// Waits until the channel goes over its high watermark, or until it is
+    /// closed. Completes immediately if it is already over it.
+    pub async fn wait_over_high_watermark(&self) {
+        self.0.wait_over_high_watermark().await
+    }
+
+    /// Waits until the channel is no longer over its high watermark, or
+    /// until it is closed. Completes immediately if it is not over it.
+    pub async fn wait_below_low_watermark(&self) {
+        self.0.wait_below_low_watermark().await
+    }
+
+    /// Ends the batch a [`Batched`] receiver is collecting, even if it is not
+    /// full. If nothing is collected yet, the next batch ends with its first
+    /// value.
+    pub fn flush(&self) {
+        self.0.flush()
+    }
+
     pub fn same_channel(&self, other: &Self) -> bool {
         self.0.same_channel(&other.0)
     }
@@ -37,13 +125,20 @@ impl<T> Tx<T> {
     }
 }
 
-impl<T> Clone for Tx<T> {
+impl<T, E> Clone for Tx<T, E> {
     fn clone(&self) -> Self {
         Self(self.0.clone())
     }
 }
 
-impl<T> Rx<T> {
+impl<T, E> Rx<T, E> {
+    /// Receives the next value, or `None` once the channel is closed and
+    /// empty.
+    ///
+    /// This does not tell why the channel ended, [`Rx::recv_result`] and
+    /// [`Rx::try_recv`] return the reason given to [`Tx::close_with`]. `recv`
+    /// keeps returning an `Option` so that receiving works the same for every
+    /// channel, e.g. in a [`RxSet`](super::rx_set::RxSet).
     pub async fn recv(&mut self) -> Option<T> {
         poll_fn(|cx| self.poll_recv(cx)).await
     }
@@ -52,10 +147,68 @@ impl<T> Rx<T> {
         self.0.recv(cx)
     }
 
-    pub fn try_recv(&mut self) -> Result<T, TryRecvError> {
+    /// Like `recv`, but tells apart why the channel ended.
+    pub async fn recv_result(&mut self) -> Result<T, RecvError<E>> {
+        poll_fn(|cx| self.poll_recv_result(cx)).await
+    }
+
+    pub fn poll_recv_result(&mut self, cx: &mut Context<'_>) -> Poll<Result<T, RecvError<E>>> {
+        self.0.recv_result(cx)
+    }
+
+    /// Receives a value if one is queued. Once the channel has ended, tells
+    /// apart why like [`Rx::recv_result`].
+    pub fn try_recv(&mut self) -> Result<T, TryRecvError<E>> {
         self.0.try_recv()
     }
 
+    /// Receives the first value for which `f` returns `true`, leaving the
+    /// other values queued in order.
+    ///
+    /// Returns `None` if the channel is closed and no queued value matches.
+    pub async fn recv_matching<F>(&mut self, mut f: F) -> Option<T>
+    where
+        F: FnMut(&T) -> bool,
+    {
+        let mut scanned = 0;
+        poll_fn(|cx| self.0.recv_matching(cx, &mut scanned, &mut f)).await
+    }
+
+    pub fn try_recv_matching<F>(&mut self, f: F) -> Result<T, TryRecvError>
+    where
+        F: FnMut(&T) -> bool,
+    {
+        self.0.try_recv_matching(f)
+    }
+
+    /// Returns a reference to the next value without receiving it.
+    ///
+    /// The channel can not be sent to while the returned `Ref` is alive,
+    /// sending in the meantime will panic.
+    pub fn peek(&self) -> Option<Ref<'_, T>> {
+        self.0.peek()
+    }
+
+    /// Receives all values currently queued without waiting. Each value
+    /// frees its slot as soon as it is yielded.
+    pub fn drain(&mut self) -> impl Iterator<Item = T> + '_ {
+        self.0.drain()
+    }
+
+    /// Retains only the queued values for which `f` returns `true`, and
+    /// frees the slots of the removed ones. Order is preserved.
+    pub fn retain<F>(&mut self, f: F)
+    where
+        F: FnMut(&T) -> bool,
+    {
+        self.0.retain(f)
+    }
+
+    /// Closes the channel without dropping the receiver.
+    ///
+    /// Later sends fail and give the value back, while values already queued
+    /// can still be received. Once the queue is empty, `recv` returns `None`
+    /// and `try_recv` returns `Disconnected`, even if senders are still alive.
     pub fn close(&self) {
         self.0.close()
     }
@@ -64,14 +217,69 @@ impl<T> Rx<T> {
         self.0.is_closed()
     }
 
+    /// Turns the receiver into one which yields values in batches of up to
+    /// `max_items` values.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max_items` is 0.
+    pub fn batched(self, max_items: usize) -> Batched<T, E> {
+        Batched::new(self, max_items)
+    }
+
+    /// Splits the receiver into `n` receivers which each get a clone of every
+    /// value, see [`tee`](super::tee::tee).
+    pub fn tee(self, n: usize, capacity: usize, policy: tee::Policy) -> Vec<tee::Rx<T>>
+    where
+        T: Clone + 'static,
+        E: 'static,
+    {
+        tee::tee(self, n, capacity, policy)
+    }
+
+    /// Sets which panicking senders poison the channel, the default is
+    /// [`PanicPolicy::LastSender`].
+    pub fn set_panic_policy(&self, policy: PanicPolicy) {
+        self.0.set_panic_policy(policy)
+    }
+
+    /// Returns `true` if a sender has been dropped while panicking. Once the
+    /// queue is drained, `recv_result` and `try_recv` return `SenderPanicked`.
+    pub fn is_poisoned(&self) -> bool {
+        self.0.is_poisoned()
+    }
+
     pub fn hint(&self) -> usize {
         self.0.hint()
     }
 }
 
+impl<T, E> Batched<T, E> {
+    /// Waits for a batch and appends it to `buf`, so the same buffer can be
+    /// reused for every batch. Returns the number of values appended.
+    ///
+    /// A batch ends once it is full, a sender calls `flush`, or the channel is
+    /// closed. A `flush` while no value is collected yet ends the batch as
+    /// soon as it has one. Returns 0 once the channel is closed and all
+    /// values have been received.
+    ///
+    /// If the future is dropped, the values already moved to `buf` stay
+    /// there and still count towards the batch.
+    pub async fn recv_batch(&mut self, buf: &mut Vec<T>) -> usize {
+        poll_fn(|cx| self.poll_recv_batch(cx, buf)).await
+    }
+
+    pub fn poll_recv_batch(&mut self, cx: &mut Context<'_>, buf: &mut Vec<T>) -> Poll<usize> {
+        let (rx, batch) = self.parts_mut();
+        rx.0.recv_batch(cx, buf, batch)
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use super::channel;
+    use super::{channel, channel_with_watermarks};
+    use crate::mpsc::{SendError, TryRecvError};
+    use futures_lite::future::{poll_once, yield_now};
 
     #[monoio::test]
     async fn tets_unbounded_channel() {
@@ -82,4 +290,142 @@ mod tests {
         drop(tx);
         assert_eq!(rx.recv().await, None);
     }
+
+    #[monoio::test]
+    async fn test_unbounded_channel_rx_close() {
+        let (tx, mut rx) = channel();
+        tx.send(1).unwrap();
+
+        // a pending recv ends once the channel is closed and drained
+        let join = monoio::spawn(async move {
+            assert_eq!(rx.recv().await, Some(1));
+            assert_eq!(rx.recv().await, None);
+        });
+        yield_now().await;
+        tx.close();
+        assert_eq!(tx.send(2), Err(SendError::RxClosed(2)));
+        join.await;
+    }
+
+    #[monoio::test]
+    async fn test_unbounded_channel_recv_matching() {
+        let (tx, mut rx) = channel();
+        tx.send(1).unwrap();
+        tx.send(2).unwrap();
+        assert_eq!(rx.try_recv_matching(|v| *v == 3), Err(TryRecvError::Empty));
+
+        let tx2 = tx.clone();
+        let join = monoio::spawn(async move {
+            tx2.send(3).unwrap();
+            tx2.send(4).unwrap();
+        });
+        assert_eq!(rx.recv_matching(|v| v % 2 == 0 && *v > 2).await, Some(4));
+        join.await;
+        assert_eq!(rx.try_recv_matching(|v| *v == 2), Ok(2));
+
+        drop(tx);
+        assert_eq!(rx.recv_matching(|v| *v > 10).await, None);
+        assert_eq!(rx.drain().collect::<Vec<_>>(), vec![1, 3]);
+    }
+
+    #[monoio::test]
+    async fn test_unbounded_channel_tx_closed() {
+        let (tx, rx) = channel::<u32>();
+        let tx2 = tx.clone();
+        let join = monoio::spawn(async move {
+            tx2.closed().await;
+            assert!(tx2.is_closed());
+        });
+        yield_now().await;
+        drop(rx);
+        join.await;
+        // resolves immediately once closed
+        tx.closed().await;
+    }
+
+    #[monoio::test]
+    async fn test_unbounded_channel_watermarks() {
+        let (tx, mut rx) = channel_with_watermarks(1, 3);
+        for i in 0..3 {
+            tx.send(i).unwrap();
+        }
+        assert!(!tx.is_over_high_watermark());
+        tx.wait_below_low_watermark().await;
+        let watcher = tx.clone();
+        let watch = monoio::spawn(async move {
+            watcher.wait_over_high_watermark().await;
+            watcher.is_over_high_watermark()
+        });
+        yield_now().await;
+        tx.send(3).unwrap();
+        assert!(tx.is_over_high_watermark());
+        assert!(watch.await);
+
+        let tx2 = tx.clone();
+        let join = monoio::spawn(async move {
+            tx2.wait_below_low_watermark().await;
+            tx2.send(4).unwrap();
+        });
+        yield_now().await;
+        assert_eq!(rx.recv().await, Some(0));
+        assert_eq!(rx.recv().await, Some(1));
+        // between the watermarks the signal does not flap
+        assert!(tx.is_over_high_watermark());
+        assert_eq!(rx.recv().await, Some(2));
+        assert!(!tx.is_over_high_watermark());
+        join.await;
+        assert_eq!(rx.recv().await, Some(3));
+        assert_eq!(rx.recv().await, Some(4));
+    }
+
+    #[monoio::test]
+    async fn test_unbounded_channel_batched() {
+        let (tx, rx) = channel();
+        let mut rx = rx.batched(3);
+        let mut buf = Vec::new();
+        for i in 0..5 {
+            tx.send(i).unwrap();
+        }
+        assert_eq!(rx.recv_batch(&mut buf).await, 3);
+        assert_eq!(buf, [0, 1, 2]);
+
+        // a flush ends a batch which is not full
+        buf.clear();
+        let tx2 = tx.clone();
+        let join = monoio::spawn(async move {
+            tx2.send(5).unwrap();
+            tx2.flush();
+            tx2.send(6).unwrap();
+        });
+        assert_eq!(rx.recv_batch(&mut buf).await, 3);
+        assert_eq!(buf, [3, 4, 5]);
+        join.await;
+
+        // and so does dropping the last sender
+        buf.clear();
+        drop(tx);
+        assert_eq!(rx.recv_batch(&mut buf).await, 1);
+        assert_eq!(buf, [6]);
+        assert_eq!(rx.recv_batch(&mut buf).await, 0);
+    }
+
+    #[monoio::test]
+    async fn test_unbounded_channel_batched_flush_empty() {
+        let (tx, rx) = channel();
+        let mut rx = rx.batched(3);
+        let mut buf = Vec::new();
+
+        // a flush while nothing is queued ends the next batch
+        tx.flush();
+        tx.send(0).unwrap();
+        assert_eq!(rx.recv_batch(&mut buf).await, 1);
+        assert_eq!(buf, [0]);
+
+        // dropping a sender which is not the last one does not flush
+        buf.clear();
+        drop(tx.clone());
+        tx.send(1).unwrap();
+        assert!(poll_once(rx.recv_batch(&mut buf)).await.is_none());
+        assert_eq!(buf, [1]);
+    }
 }
@@ -0,0 +1,211 @@
+//! Notify inspired by tokio.
+//!
+//! A list of waiting tasks which are all woken at once. Waiters are stored in
+//! an intrusive linked list, so any number of tasks can wait without
+//! allocating.
+
+use std::{
+    cell::{Cell, RefCell, UnsafeCell},
+    future::Future,
+    marker::PhantomPinned,
+    pin::Pin,
+    ptr::NonNull,
+    task::{Context, Poll, Waker},
+};
+
+use crate::{
+    linked_list::{self, LinkedList},
+    wake_list::WakeList,
+};
+
+pub(crate) struct Notify {
+    waiters: RefCell<LinkedList<Waiter, <Waiter as linked_list::Link>::Target>>,
+}
+
+pub(crate) struct Waiter {
+    /// The waker to notify the waiting task.
+    ///
+    /// # Safety
+    ///
+    /// This may only be accessed while the wait list is borrowed.
+    waker: UnsafeCell<Option<Waker>>,
+
+    /// Whether the waiter is in the wait list.
+    queued: Cell<bool>,
+
+    /// Whether the waiter has been notified since it was registered.
+    notified: Cell<bool>,
+
+    /// Intrusive linked-list pointers.
+    pointers: linked_list::Pointers<Waiter>,
+
+    /// Should not be `Unpin`.
+    _p: PhantomPinned,
+}
+
+/// Future returned by [`Notify::notified`].
+pub(crate) struct Notified<'a> {
+    notify: &'a Notify,
+    waiter: Waiter,
+}
+
+impl Waiter {
+    pub(crate) fn new() -> Self {
+        Self {
+            waker: UnsafeCell::new(None),
+            queued: Cell::new(false),
+            notified: Cell::new(false),
+            pointers: linked_list::Pointers::new(),
+            _p: PhantomPinned,
+        }
+    }
+}
+
+unsafe impl linked_list::Link for Waiter {
+    type Handle = NonNull<Waiter>;
+    type Target = Waiter;
+
+    fn as_raw(handle: &Self::Handle) -> NonNull<Waiter> {
+        *handle
+    }
+
+    unsafe fn from_raw(ptr: NonNull<Waiter>) -> NonNull<Waiter> {
+        ptr
+    }
+
+    unsafe fn pointers(mut target: NonNull<Waiter>) -> NonNull<linked_list::Pointers<Waiter>> {
+        NonNull::from(&mut target.as_mut().pointers)
+    }
+}
+
+impl Notify {
+    pub(crate) const fn new() -> Self {
+        Self {
+            waiters: RefCell::new(LinkedList::new()),
+        }
+    }
+
+    /// Returns a future which completes on the next `notify_waiters`.
+    ///
+    /// The future registers itself when it is first polled.
+    pub(crate) fn notified(&self) -> Notified<'_> {
+        Notified {
+            notify: self,
+            waiter: Waiter::new(),
+        }
+    }
+
+    /// Register the waiter to be woken by the next `notify_waiters`, or only
+    /// update its waker if it is already registered.
+    ///
+    /// # Safety
+    ///
+    /// The waiter must be removed with `remove` before it is dropped.
+    pub(crate) unsafe fn register(&self, waiter: Pin<&mut Waiter>, waker: &Waker) {
+        {
+            let slot = &mut *waiter.waker.get();
+            if !slot.as_ref().map(|w| w.will_wake(waker)).unwrap_or(false) {
+                *slot = Some(waker.clone());
+            }
+        }
+        if !waiter.queued.replace(true) {
+            waiter.notified.set(false);
+            let node = NonNull::new_unchecked(Pin::into_inner_unchecked(waiter) as *mut Waiter);
+            self.waiters.borrow_mut().push_front(node);
+        }
+    }
+
+    /// Remove the waiter from the wait list if it is registered.
+    pub(crate) fn remove(&self, waiter: Pin<&mut Waiter>) {
+        if waiter.queued.replace(false) {
+            let node = unsafe { NonNull::from(Pin::into_inner_unchecked(waiter)) };
+            // Safety: the waiter is queued, so it is in this list.
+            unsafe { self.waiters.borrow_mut().remove(node) };
+        }
+    }
+
+    /// Wake all registered waiters.
+    pub(crate) fn notify_waiters(&self) {
+        let mut wakers = WakeList::new();
+        loop {
+            let mut waiters = self.waiters.borrow_mut();
+            while wakers.can_push() {
+                let waiter = match waiters.pop_back() {
+                    Some(waiter) => unsafe { waiter.as_ref() },
+                    None => break,
+                };
+                waiter.queued.set(false);
+                waiter.notified.set(true);
+                if let Some(waker) = unsafe { (*waiter.waker.get()).take() } {
+                    wakers.push(waker);
+                }
+            }
+            let done = waiters.is_empty();
+            // Wake outside of the borrow, wakers may register again.
+            drop(waiters);
+            wakers.wake_all();
+            if done {
+                return;
+            }
+        }
+    }
+}
+
+impl Notified<'_> {
+    fn project(self: Pin<&mut Self>) -> (&Notify, Pin<&mut Waiter>) {
+        unsafe {
+            // Safety: `notify` is `Unpin`, only `waiter` is structurally pinned.
+            let this = self.get_unchecked_mut();
+            (this.notify, Pin::new_unchecked(&mut this.waiter))
+        }
+    }
+}
+
+impl Future for Notified<'_> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let (notify, waiter) = self.project();
+        if waiter.notified.get() {
+            return Poll::Ready(());
+        }
+        // Safety: the waiter is removed in `Drop for Notified`.
+        unsafe { notify.register(waiter, cx.waker()) };
+        Poll::Pending
+    }
+}
+
+impl Drop for Notified<'_> {
+    fn drop(&mut self) {
+        // This is where we ensure safety. The future is being dropped, which
+        // means we must ensure that the waiter entry is no longer stored in
+        // the linked list.
+        let waiter = unsafe { Pin::new_unchecked(&mut self.waiter) };
+        self.notify.remove(waiter);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Notify;
+    use futures_lite::future::poll_once;
+    use std::pin::pin;
+
+    #[monoio::test]
+    async fn notify_waiters() {
+        let notify = Notify::new();
+        let mut a = pin!(notify.notified());
+        let mut b = pin!(notify.notified());
+        assert!(poll_once(a.as_mut()).await.is_none());
+        assert!(poll_once(b.as_mut()).await.is_none());
+        {
+            // a dropped waiter leaves the list
+            let mut c = pin!(notify.notified());
+            assert!(poll_once(c.as_mut()).await.is_none());
+        }
+        notify.notify_waiters();
+        a.await;
+        b.await;
+        assert!(notify.waiters.borrow().is_empty());
+    }
+}
@@ -129,7 +129,14 @@ pub mod error {
 
     /// Error returned by the `Future` implementation for `Receiver`.
     #[derive(Debug, Eq, PartialEq)]
-    pub struct RecvError(pub(super) ());
+    pub enum RecvError {
+        /// The send half of the channel was dropped without sending a value.
+        Closed,
+
+        /// The send half of the channel was dropped without sending a value
+        /// while its task was panicking.
+        SenderPanicked,
+    }
 
     /// Error returned by the `try_recv` function on `Receiver`.
     #[derive(Debug, Eq, PartialEq)]
@@ -139,13 +146,20 @@ pub mod error {
 
         /// The send half of the channel was dropped without sending a value.
         Closed,
+
+        /// The send half of the channel was dropped without sending a value
+        /// while its task was panicking.
+        SenderPanicked,
     }
 
     // ===== impl RecvError =====
 
     impl fmt::Display for RecvError {
         fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
-            write!(fmt, "channel closed")
+            match self {
+                RecvError::Closed => write!(fmt, "channel closed"),
+                RecvError::SenderPanicked => write!(fmt, "sender panicked"),
+            }
         }
     }
 
@@ -158,6 +172,7 @@ pub mod error {
             match self {
                 TryRecvError::Empty => write!(fmt, "channel empty"),
                 TryRecvError::Closed => write!(fmt, "channel closed"),
+                TryRecvError::SenderPanicked => write!(fmt, "sender panicked"),
             }
         }
     }
@@ -510,6 +525,9 @@ impl<T> Sender<T> {
 impl<T> Drop for Sender<T> {
     fn drop(&mut self) {
         if let Some(inner) = self.inner.as_ref() {
+            if std::thread::panicking() {
+                State::set_sender_panicked(&inner.state);
+            }
             inner.complete();
         }
     }
@@ -605,6 +623,8 @@ impl<T> Receiver<T> {
     /// - `Err(TryRecvError::Empty)` if no value has been sent yet.
     /// - `Err(TryRecvError::Closed)` if the sender has dropped without sending
     ///   a value.
+    /// - `Err(TryRecvError::SenderPanicked)` if the sender has dropped without
+    ///   sending a value while its task was panicking.
     ///
     /// # Examples
     ///
@@ -660,10 +680,10 @@ impl<T> Receiver<T> {
             if state.is_complete() {
                 match unsafe { inner.consume_value() } {
                     Some(value) => Ok(value),
-                    None => Err(TryRecvError::Closed),
+                    None => Err(state.try_recv_error()),
                 }
             } else if state.is_closed() {
-                Err(TryRecvError::Closed)
+                Err(state.try_recv_error())
             } else {
                 // Not ready, this does not clear `inner`
                 return Err(TryRecvError::Empty);
@@ -726,10 +746,10 @@ impl<T> Inner<T> {
         if state.is_complete() {
             match unsafe { self.consume_value() } {
                 Some(value) => Ready(Ok(value)),
-                None => Ready(Err(RecvError(()))),
+                None => Ready(Err(State(*self.state.borrow()).recv_error())),
             }
         } else if state.is_closed() {
-            Ready(Err(RecvError(())))
+            Ready(Err(State(*self.state.borrow()).recv_error()))
         } else {
             if state.is_rx_task_set() {
                 let will_notify = unsafe { self.rx_task.will_wake(cx) };
@@ -744,7 +764,7 @@ impl<T> Inner<T> {
 
                         return match unsafe { self.consume_value() } {
                             Some(value) => Ready(Ok(value)),
-                            None => Ready(Err(RecvError(()))),
+                            None => Ready(Err(State(*self.state.borrow()).recv_error())),
                         };
                     } else {
                         unsafe { self.rx_task.drop_task() };
@@ -764,7 +784,7 @@ impl<T> Inner<T> {
                 if state.is_complete() {
                     match unsafe { self.consume_value() } {
                         Some(value) => Ready(Ok(value)),
-                        None => Ready(Err(RecvError(()))),
+                        None => Ready(Err(State(*self.state.borrow()).recv_error())),
                     }
                 } else {
                     Pending
@@ -823,6 +843,7 @@ const RX_TASK_SET: usize = 0b00001;
 const VALUE_SENT: usize = 0b00010;
 const CLOSED: usize = 0b00100;
 const TX_TASK_SET: usize = 0b01000;
+const SENDER_PANICKED: usize = 0b10000;
 
 impl State {
     fn new() -> State {
@@ -883,6 +904,32 @@ impl State {
         self.0 & TX_TASK_SET == TX_TASK_SET
     }
 
+    fn is_sender_panicked(self) -> bool {
+        self.0 & SENDER_PANICKED == SENDER_PANICKED
+    }
+
+    fn set_sender_panicked(cell: &RefCell<usize>) -> State {
+        let mut val = cell.borrow_mut();
+        *val |= SENDER_PANICKED;
+        State(*val)
+    }
+
+    /// The error to report when the value will never be received.
+    fn recv_error(self) -> RecvError {
+        if self.is_sender_panicked() {
+            RecvError::SenderPanicked
+        } else {
+            RecvError::Closed
+        }
+    }
+
+    fn try_recv_error(self) -> TryRecvError {
+        match self.recv_error() {
+            RecvError::Closed => TryRecvError::Closed,
+            RecvError::SenderPanicked => TryRecvError::SenderPanicked,
+        }
+    }
+
     fn as_usize(self) -> usize {
         self.0
     }
@@ -895,6 +942,7 @@ impl fmt::Debug for State {
             .field("is_closed", &self.is_closed())
             .field("is_rx_task_set", &self.is_rx_task_set())
             .field("is_tx_task_set", &self.is_tx_task_set())
+            .field("is_sender_panicked", &self.is_sender_panicked())
             .finish()
     }
 }
@@ -902,6 +950,7 @@ impl fmt::Debug for State {
 #[cfg(test)]
 mod tests {
     use super::channel;
+    use super::error::{RecvError, TryRecvError};
 
     #[monoio::test]
     async fn it_works() {
@@ -910,4 +959,18 @@ mod tests {
         tx.send(1).unwrap();
         assert_eq!(join.await.unwrap(), 1);
     }
+
+    #[monoio::test]
+    async fn sender_panicked() {
+        let (tx, rx) = channel::<u32>();
+        let _ = std::panic::catch_unwind(std::panic::AssertUnwindSafe(move || {
+            let _tx = tx;
+            panic!("sender task crashed");
+        }));
+        assert_eq!(rx.await, Err(RecvError::SenderPanicked));
+
+        let (tx, mut rx) = channel::<u32>();
+        drop(tx);
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Closed));
+    }
 }
@@ -0,0 +1,452 @@
+//! In-memory byte pipe implementing the `futures-io` traits.
+//!
+//! Bytes are stored in fixed size blocks, like the mpsc queue does for
+//! values. The reader takes a whole block at a time so that it can lend it out
+//! from `poll_fill_buf`, and gives it back for the writer to reuse once it has
+//! been consumed. At most `capacity` bytes are buffered, a writer finding the
+//! pipe full waits for the reader.
+//!
+//! Closing or dropping the writer is seen by the reader as EOF once it has
+//! read what was written before. Writing after the reader is dropped fails
+//! with `BrokenPipe`.
+
+use futures_io::{AsyncBufRead, AsyncRead, AsyncWrite};
+use std::{
+    cell::RefCell,
+    collections::VecDeque,
+    io::{self, IoSlice},
+    pin::Pin,
+    rc::Rc,
+    task::{Context, Poll, Waker},
+};
+
+const BLOCK_CAP: usize = 4096;
+
+/// Creates a pipe buffering up to `capacity` bytes.
+///
+/// # Panics
+///
+/// Panics if `capacity` is 0.
+pub fn pipe(capacity: usize) -> (PipeWriter, PipeReader) {
+    assert!(capacity > 0, "pipe capacity must be greater than 0");
+    let shared = Rc::new(Shared {
+        state: RefCell::new(State {
+            blocks: VecDeque::new(),
+            spare: None,
+            len: 0,
+            writer_closed: false,
+            reader_closed: false,
+            read_waker: None,
+            write_waker: None,
+        }),
+        capacity,
+    });
+    let writer = PipeWriter {
+        shared: shared.clone(),
+    };
+    let reader = PipeReader {
+        shared,
+        block: None,
+    };
+    (writer, reader)
+}
+
+/// Creates a connected pair of streams, each direction buffers up to
+/// `capacity` bytes.
+///
+/// # Panics
+///
+/// Panics if `capacity` is 0.
+pub fn duplex_stream(capacity: usize) -> (DuplexStream, DuplexStream) {
+    let (a_writer, a_reader) = pipe(capacity);
+    let (b_writer, b_reader) = pipe(capacity);
+    let left = DuplexStream {
+        reader: b_reader,
+        writer: a_writer,
+    };
+    let right = DuplexStream {
+        reader: a_reader,
+        writer: b_writer,
+    };
+    (left, right)
+}
+
+pub struct PipeWriter {
+    shared: Rc<Shared>,
+}
+
+pub struct PipeReader {
+    shared: Rc<Shared>,
+    /// The block being read, taken from the pipe.
+    block: Option<Block>,
+}
+
+/// One end of a [`duplex_stream`], reading what the other end writes.
+pub struct DuplexStream {
+    reader: PipeReader,
+    writer: PipeWriter,
+}
+
+struct Block {
+    data: Box<[u8; BLOCK_CAP]>,
+    /// Head index.
+    begin: usize,
+    /// Tail index.
+    end: usize,
+}
+
+impl Block {
+    fn is_empty(&self) -> bool {
+        self.begin == self.end
+    }
+}
+
+struct Shared {
+    state: RefCell<State>,
+    capacity: usize,
+}
+
+struct State {
+    blocks: VecDeque<Block>,
+    /// A consumed block kept for the next write.
+    spare: Option<Box<[u8; BLOCK_CAP]>>,
+    /// Bytes buffered, including the unread part of the reader's block.
+    len: usize,
+    writer_closed: bool,
+    reader_closed: bool,
+    read_waker: Option<Waker>,
+    write_waker: Option<Waker>,
+}
+
+impl State {
+    fn new_block(&mut self) -> Block {
+        let data = self
+            .spare
+            .take()
+            .unwrap_or_else(|| Box::new([0; BLOCK_CAP]));
+        Block {
+            data,
+            begin: 0,
+            end: 0,
+        }
+    }
+
+    fn wake_reader(&mut self) {
+        if let Some(waker) = self.read_waker.take() {
+            waker.wake();
+        }
+    }
+
+    fn wake_writer(&mut self) {
+        if let Some(waker) = self.write_waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+fn register(slot: &mut Option<Waker>, cx: &mut Context<'_>) {
+    match slot.as_mut() {
+        Some(inner) => {
+            if !inner.will_wake(cx.waker()) {
+                *inner = cx.waker().clone();
+            }
+        }
+        None => {
+            *slot = Some(cx.waker().clone());
+        }
+    }
+}
+
+impl PipeWriter {
+    fn write_slices(&self, cx: &mut Context<'_>, bufs: &[IoSlice<'_>]) -> Poll<io::Result<usize>> {
+        let mut state = self.shared.state.borrow_mut();
+        if state.reader_closed || state.writer_closed {
+            return Poll::Ready(Err(io::ErrorKind::BrokenPipe.into()));
+        }
+        if bufs.iter().all(|buf| buf.is_empty()) {
+            return Poll::Ready(Ok(0));
+        }
+        let available = self.shared.capacity - state.len;
+        if available == 0 {
+            register(&mut state.write_waker, cx);
+            return Poll::Pending;
+        }
+
+        let mut written = 0;
+        for buf in bufs {
+            let mut buf = &buf[..buf.len().min(available - written)];
+            while !buf.is_empty() {
+                if state
+                    .blocks
+                    .back()
+                    .map(|b| b.end == BLOCK_CAP)
+                    .unwrap_or(true)
+                {
+                    let block = state.new_block();
+                    state.blocks.push_back(block);
+                }
+                let block = state.blocks.back_mut().unwrap();
+                let n = buf.len().min(BLOCK_CAP - block.end);
+                block.data[block.end..block.end + n].copy_from_slice(&buf[..n]);
+                block.end += n;
+                buf = &buf[n..];
+                written += n;
+            }
+            if written == available {
+                break;
+            }
+        }
+        state.len += written;
+        state.wake_reader();
+        Poll::Ready(Ok(written))
+    }
+
+    fn shutdown(&self) {
+        let mut state = self.shared.state.borrow_mut();
+        state.writer_closed = true;
+        state.wake_reader();
+    }
+}
+
+impl AsyncWrite for PipeWriter {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.write_slices(cx, &[IoSlice::new(buf)])
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        self.write_slices(cx, bufs)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    /// The reader gets EOF once it has read what was written before.
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.shutdown();
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl Drop for PipeWriter {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+impl AsyncBufRead for PipeReader {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        let this = self.get_mut();
+        if this.block.as_ref().map(Block::is_empty).unwrap_or(true) {
+            let mut state = this.shared.state.borrow_mut();
+            if let Some(block) = this.block.take() {
+                state.spare = Some(block.data);
+            }
+            match state.blocks.pop_front() {
+                Some(block) => this.block = Some(block),
+                None if state.writer_closed => return Poll::Ready(Ok(&[])),
+                None => {
+                    register(&mut state.read_waker, cx);
+                    return Poll::Pending;
+                }
+            }
+        }
+        let block = this.block.as_ref().unwrap();
+        Poll::Ready(Ok(&block.data[block.begin..block.end]))
+    }
+
+    fn consume(self: Pin<&mut Self>, amt: usize) {
+        if amt == 0 {
+            return;
+        }
+        let this = self.get_mut();
+        let block = this.block.as_mut().expect("consume without fill_buf");
+        assert!(amt <= block.end - block.begin, "consume beyond buffer");
+        block.begin += amt;
+        let mut state = this.shared.state.borrow_mut();
+        state.len -= amt;
+        state.wake_writer();
+    }
+}
+
+impl AsyncRead for PipeReader {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+        let n = match self.as_mut().poll_fill_buf(cx) {
+            Poll::Ready(Ok(data)) => {
+                let n = data.len().min(buf.len());
+                buf[..n].copy_from_slice(&data[..n]);
+                n
+            }
+            Poll::Ready(Err(err)) => return Poll::Ready(Err(err)),
+            Poll::Pending => return Poll::Pending,
+        };
+        self.consume(n);
+        Poll::Ready(Ok(n))
+    }
+}
+
+impl Drop for PipeReader {
+    fn drop(&mut self) {
+        let mut state = self.shared.state.borrow_mut();
+        state.reader_closed = true;
+        state.blocks.clear();
+        state.len = 0;
+        state.wake_writer();
+    }
+}
+
+impl DuplexStream {
+    /// Splits the stream into its reading and writing halves.
+    pub fn split(self) -> (PipeReader, PipeWriter) {
+        (self.reader, self.writer)
+    }
+}
+
+impl AsyncRead for DuplexStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.reader).poll_read(cx, buf)
+    }
+}
+
+impl AsyncBufRead for DuplexStream {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        Pin::new(&mut self.get_mut().reader).poll_fill_buf(cx)
+    }
+
+    fn consume(mut self: Pin<&mut Self>, amt: usize) {
+        Pin::new(&mut self.reader).consume(amt)
+    }
+}
+
+impl AsyncWrite for DuplexStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.writer).poll_write(cx, buf)
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.writer).poll_write_vectored(cx, bufs)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.writer).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.writer).poll_close(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{duplex_stream, pipe, BLOCK_CAP};
+    use futures_io::{AsyncBufRead, AsyncRead, AsyncWrite};
+    use futures_lite::future::{poll_fn, poll_once};
+    use std::{
+        io::{ErrorKind, IoSlice},
+        pin::Pin,
+    };
+
+    async fn write<W: AsyncWrite + Unpin>(w: &mut W, buf: &[u8]) -> usize {
+        poll_fn(|cx| Pin::new(&mut *w).poll_write(cx, buf))
+            .await
+            .unwrap()
+    }
+
+    async fn read<R: AsyncRead + Unpin>(r: &mut R, buf: &mut [u8]) -> usize {
+        poll_fn(|cx| Pin::new(&mut *r).poll_read(cx, buf))
+            .await
+            .unwrap()
+    }
+
+    #[monoio::test]
+    async fn test_pipe() {
+        let (mut writer, mut reader) = pipe(BLOCK_CAP + 10);
+        let data: Vec<u8> = (0..BLOCK_CAP + 20).map(|i| i as u8).collect();
+        // only capacity bytes fit, across two blocks
+        assert_eq!(write(&mut writer, &data).await, BLOCK_CAP + 10);
+        let mut pending = Box::pin(write(&mut writer, &data[BLOCK_CAP + 10..]));
+        assert!(poll_once(pending.as_mut()).await.is_none());
+
+        let mut buf = vec![0; BLOCK_CAP + 20];
+        assert_eq!(read(&mut reader, &mut buf[..BLOCK_CAP]).await, BLOCK_CAP);
+        assert_eq!(pending.await, 10);
+        assert_eq!(read(&mut reader, &mut buf[BLOCK_CAP..]).await, 20);
+        assert_eq!(buf, data);
+
+        // shutdown is EOF after the buffered bytes
+        write(&mut writer, b"end").await;
+        poll_fn(|cx| Pin::new(&mut writer).poll_close(cx))
+            .await
+            .unwrap();
+        let filled = poll_fn(|cx| {
+            Pin::new(&mut reader)
+                .poll_fill_buf(cx)
+                .map_ok(|b| b.to_vec())
+        })
+        .await
+        .unwrap();
+        assert_eq!(filled, b"end");
+        Pin::new(&mut reader).consume(3);
+        assert_eq!(read(&mut reader, &mut buf).await, 0);
+    }
+
+    #[monoio::test]
+    async fn test_pipe_vectored() {
+        let (mut writer, mut reader) = pipe(5);
+        let bufs = [IoSlice::new(b"abc"), IoSlice::new(b"def")];
+        let n = poll_fn(|cx| Pin::new(&mut writer).poll_write_vectored(cx, &bufs))
+            .await
+            .unwrap();
+        assert_eq!(n, 5);
+        let mut buf = [0; 8];
+        assert_eq!(read(&mut reader, &mut buf).await, 5);
+        assert_eq!(&buf[..5], b"abcde");
+
+        drop(reader);
+        let err = poll_fn(|cx| Pin::new(&mut writer).poll_write(cx, b"f"))
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::BrokenPipe);
+    }
+
+    #[monoio::test]
+    async fn test_duplex_stream() {
+        let (mut left, mut right) = duplex_stream(16);
+        write(&mut left, b"ping").await;
+        let mut buf = [0; 4];
+        assert_eq!(read(&mut right, &mut buf).await, 4);
+        assert_eq!(&buf, b"ping");
+        write(&mut right, b"pong").await;
+        drop(right);
+        assert_eq!(read(&mut left, &mut buf).await, 4);
+        assert_eq!(&buf, b"pong");
+        assert_eq!(read(&mut left, &mut buf).await, 0);
+    }
+}
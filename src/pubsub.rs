@@ -0,0 +1,295 @@
+//! Topic based publish/subscribe bus.
+//!
+//! Topics are made of segments separated by `.`, like `orders.eu.created`.
+//! A subscription pattern matches a topic segment by segment, where `*`
+//! matches any one segment and a trailing `#` matches all the remaining
+//! segments, if any. So `orders.*.created` and `orders.#` both match the topic
+//! above, and `#` matches every topic. A `#` which is not the last segment
+//! only matches a literal `#` segment.
+//!
+//! Every subscriber has its own bounded buffer. Publishing never waits: when
+//! the buffer of a slow subscriber is full its oldest value is dropped and
+//! counted, see [`Subscriber::lagged`].
+
+use crate::mpsc::rx_set::Receiver;
+use futures_lite::future::poll_fn;
+use std::{
+    cell::{Cell, RefCell},
+    collections::VecDeque,
+    rc::{Rc, Weak},
+    task::{Context, Poll, Waker},
+};
+
+type Subscriptions<T> = RefCell<Vec<Subscription<T>>>;
+
+pub struct PubSub<T> {
+    subscriptions: Rc<Subscriptions<T>>,
+    capacity: usize,
+}
+
+pub struct Subscriber<T> {
+    inner: Rc<Inner<T>>,
+    /// Removes the subscription when the subscriber is dropped.
+    subscriptions: Weak<Subscriptions<T>>,
+}
+
+struct Subscription<T> {
+    pattern: String,
+    inner: Rc<Inner<T>>,
+}
+
+struct Inner<T> {
+    queue: RefCell<VecDeque<T>>,
+    waker: RefCell<Option<Waker>>,
+    lagged: Cell<usize>,
+    /// Set once the bus has been dropped.
+    closed: Cell<bool>,
+}
+
+impl<T> Inner<T> {
+    fn wake(&self) {
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+/// Returns `true` if `pattern` matches `topic`.
+fn matches(pattern: &str, topic: &str) -> bool {
+    let mut topic = topic.split('.');
+    let mut pattern = pattern.split('.').peekable();
+    while let Some(segment) = pattern.next() {
+        match segment {
+            "#" if pattern.peek().is_none() => return true,
+            "*" => {
+                if topic.next().is_none() {
+                    return false;
+                }
+            }
+            _ => {
+                if topic.next() != Some(segment) {
+                    return false;
+                }
+            }
+        }
+    }
+    topic.next().is_none()
+}
+
+impl<T: Clone> PubSub<T> {
+    /// Creates a bus where every subscriber buffers up to `capacity` values.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `capacity` is 0.
+    pub fn new(capacity: usize) -> Self {
+        assert!(capacity > 0, "pubsub capacity must be greater than 0");
+        Self {
+            subscriptions: Rc::new(RefCell::new(Vec::new())),
+            capacity,
+        }
+    }
+
+    /// Returns a subscriber receiving the values published to the topics
+    /// matching `pattern`, from now on.
+    pub fn subscribe(&self, pattern: &str) -> Subscriber<T> {
+        let inner = Rc::new(Inner {
+            queue: RefCell::new(VecDeque::new()),
+            waker: RefCell::new(None),
+            lagged: Cell::new(0),
+            closed: Cell::new(false),
+        });
+        self.subscriptions.borrow_mut().push(Subscription {
+            pattern: pattern.to_owned(),
+            inner: inner.clone(),
+        });
+        Subscriber {
+            inner,
+            subscriptions: Rc::downgrade(&self.subscriptions),
+        }
+    }
+
+    /// Delivers a clone of `value` to every subscriber whose pattern matches
+    /// `topic`. Returns the number of subscribers it has been delivered to.
+    pub fn publish(&self, topic: &str, value: T) -> usize {
+        // clone and wake without holding the subscriptions, both may call
+        // back into the bus
+        let targets: Vec<_> = self
+            .subscriptions
+            .borrow()
+            .iter()
+            .filter(|sub| matches(&sub.pattern, topic))
+            .map(|sub| sub.inner.clone())
+            .collect();
+        for inner in &targets {
+            let value = value.clone();
+            let mut queue = inner.queue.borrow_mut();
+            let lagged = if queue.len() == self.capacity {
+                inner.lagged.set(inner.lagged.get() + 1);
+                queue.pop_front()
+            } else {
+                None
+            };
+            queue.push_back(value);
+            drop(queue);
+            drop(lagged);
+            inner.wake();
+        }
+        targets.len()
+    }
+
+    /// Returns the number of subscribers.
+    pub fn len(&self) -> usize {
+        self.subscriptions.borrow().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.subscriptions.borrow().is_empty()
+    }
+}
+
+impl<T> Drop for PubSub<T> {
+    fn drop(&mut self) {
+        let subscriptions = std::mem::take(&mut *self.subscriptions.borrow_mut());
+        for sub in subscriptions {
+            sub.inner.closed.set(true);
+            sub.inner.wake();
+        }
+    }
+}
+
+impl<T> Subscriber<T> {
+    /// Receives the next value. Returns `None` once the bus has been dropped
+    /// and the buffered values have been received.
+    pub async fn recv(&mut self) -> Option<T> {
+        poll_fn(|cx| self.poll_recv(cx)).await
+    }
+
+    pub fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        if let Some(val) = self.inner.queue.borrow_mut().pop_front() {
+            return Poll::Ready(Some(val));
+        }
+        if self.inner.closed.get() {
+            return Poll::Ready(None);
+        }
+        let mut borrowed = self.inner.waker.borrow_mut();
+        match borrowed.as_mut() {
+            Some(inner) => {
+                if !inner.will_wake(cx.waker()) {
+                    *inner = cx.waker().clone();
+                }
+            }
+            None => {
+                *borrowed = Some(cx.waker().clone());
+            }
+        }
+        Poll::Pending
+    }
+
+    /// Returns the number of values this subscriber missed because its
+    /// buffer was full.
+    pub fn lagged(&self) -> usize {
+        self.inner.lagged.get()
+    }
+
+    pub fn hint(&self) -> usize {
+        self.inner.queue.borrow().len()
+    }
+}
+
+impl<T> Drop for Subscriber<T> {
+    fn drop(&mut self) {
+        let subscriptions = match self.subscriptions.upgrade() {
+            Some(subscriptions) => subscriptions,
+            None => return,
+        };
+        let removed = {
+            let mut subscriptions = subscriptions.borrow_mut();
+            subscriptions
+                .iter()
+                .position(|sub| Rc::ptr_eq(&sub.inner, &self.inner))
+                .map(|index| subscriptions.swap_remove(index))
+        };
+        drop(removed);
+    }
+}
+
+impl<T> Receiver<T> for Subscriber<T> {
+    fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        Subscriber::poll_recv(self, cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{matches, PubSub};
+    use std::rc::{Rc, Weak};
+
+    #[test]
+    fn test_matches() {
+        assert!(matches("a.b", "a.b"));
+        assert!(!matches("a.b", "a.b.c"));
+        assert!(!matches("a.b.c", "a.b"));
+        assert!(matches("a.*.c", "a.b.c"));
+        assert!(!matches("a.*", "a"));
+        assert!(matches("a.#", "a"));
+        assert!(matches("a.#", "a.b.c"));
+        assert!(!matches("a.#", "ab"));
+        assert!(matches("#", "a.b"));
+        // only a trailing `#` is a wildcard
+        assert!(!matches("a.#.c", "a.x.y"));
+        assert!(!matches("a.#.c", "a.x.c"));
+        assert!(matches("a.#.c", "a.#.c"));
+    }
+
+    #[monoio::test]
+    async fn test_pubsub() {
+        let bus = PubSub::new(2);
+        let mut exact = bus.subscribe("orders.created");
+        let mut all = bus.subscribe("orders.#");
+        let mut other = bus.subscribe("users.*");
+        assert_eq!(bus.publish("orders.created", 1), 2);
+        assert_eq!(bus.publish("orders.deleted", 2), 1);
+        assert_eq!(bus.publish("users.created", 3), 1);
+        assert_eq!(exact.recv().await, Some(1));
+        assert_eq!(all.recv().await, Some(1));
+        assert_eq!(all.recv().await, Some(2));
+        assert_eq!(other.recv().await, Some(3));
+
+        // the oldest value of a full buffer is dropped
+        for i in 4..8 {
+            bus.publish("orders.created", i);
+        }
+        assert_eq!(all.lagged(), 2);
+        assert_eq!(all.recv().await, Some(6));
+        assert_eq!(all.recv().await, Some(7));
+
+        // dropped subscribers are removed right away
+        drop(exact);
+        assert_eq!(bus.len(), 2);
+        assert_eq!(bus.publish("orders.created", 8), 1);
+
+        drop(bus);
+        assert_eq!(all.recv().await, Some(8));
+        assert_eq!(all.recv().await, None);
+        assert_eq!(other.recv().await, None);
+    }
+
+    #[monoio::test]
+    async fn test_pubsub_reentrant_clone() {
+        /// Looks at the bus whenever it is cloned.
+        struct Probe(Weak<PubSub<Probe>>);
+
+        impl Clone for Probe {
+            fn clone(&self) -> Self {
+                assert_eq!(self.0.upgrade().unwrap().len(), 1);
+                Probe(self.0.clone())
+            }
+        }
+
+        let bus = Rc::new(PubSub::new(1));
+        let mut sub = bus.subscribe("a");
+        assert_eq!(bus.publish("a", Probe(Rc::downgrade(&bus))), 1);
+        assert!(sub.recv().await.is_some());
+    }
+}
@@ -0,0 +1,256 @@
+//! Request/response channel.
+//!
+//! A [`Client`] sends requests to a [`Server`] over a bounded mpsc channel.
+//! Each request comes with a [`Responder`] which sends the response back to
+//! the waiting call. The response slots work like oneshot channels, but are
+//! kept by the client and reused for its next calls instead of allocating a
+//! new one for every call.
+
+use crate::mpsc::bounded;
+use futures_lite::future::poll_fn;
+use std::{
+    cell::{Cell, RefCell},
+    error::Error,
+    fmt,
+    rc::Rc,
+    task::{Context, Poll, Waker},
+};
+
+/// Creates a request/response channel which queues up to `capacity`
+/// requests.
+pub fn channel<Req, Resp>(capacity: usize) -> (Client<Req, Resp>, Server<Req, Resp>) {
+    let (tx, rx) = bounded::channel(capacity);
+    let client = Client {
+        tx,
+        pool: RefCell::new(Vec::new()),
+    };
+    (client, Server { rx })
+}
+
+pub struct Client<Req, Resp> {
+    tx: bounded::Tx<(Req, Responder<Resp>)>,
+    /// Response slots which are free to reuse.
+    pool: RefCell<Vec<Rc<Slot<Resp>>>>,
+}
+
+pub struct Server<Req, Resp> {
+    rx: bounded::Rx<(Req, Responder<Resp>)>,
+}
+
+/// Sends the response to a request.
+pub struct Responder<Resp> {
+    slot: Rc<Slot<Resp>>,
+    sent: bool,
+}
+
+/// Error returned by [`Client::call`].
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum RpcError {
+    /// The server has been dropped or closed, the request was not sent.
+    Closed,
+    /// The responder has been dropped without sending a response.
+    Dropped,
+}
+
+impl fmt::Display for RpcError {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            RpcError::Closed => "calling a closed server".fmt(fmt),
+            RpcError::Dropped => "request dropped without response".fmt(fmt),
+        }
+    }
+}
+
+impl Error for RpcError {}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+enum State {
+    Waiting,
+    Sent,
+    Dropped,
+}
+
+struct Slot<T> {
+    state: Cell<State>,
+    value: RefCell<Option<T>>,
+    waker: RefCell<Option<Waker>>,
+}
+
+impl<T> Slot<T> {
+    fn new() -> Self {
+        Self {
+            state: Cell::new(State::Waiting),
+            value: RefCell::new(None),
+            waker: RefCell::new(None),
+        }
+    }
+
+    fn complete(&self, state: State) {
+        self.state.set(state);
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+
+    fn poll(&self, cx: &mut Context<'_>) -> Poll<Result<T, RpcError>> {
+        match self.state.get() {
+            State::Sent => {
+                let value = self.value.take().expect("response already taken");
+                Poll::Ready(Ok(value))
+            }
+            State::Dropped => Poll::Ready(Err(RpcError::Dropped)),
+            State::Waiting => {
+                let mut borrowed = self.waker.borrow_mut();
+                match borrowed.as_mut() {
+                    Some(inner) => {
+                        if !inner.will_wake(cx.waker()) {
+                            *inner = cx.waker().clone();
+                        }
+                    }
+                    None => {
+                        *borrowed = Some(cx.waker().clone());
+                    }
+                }
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl<Req, Resp> Client<Req, Resp> {
+    /// Sends a request and waits for its response.
+    pub async fn call(&self, req: Req) -> Result<Resp, RpcError> {
+        let slot = self
+            .pool
+            .borrow_mut()
+            .pop()
+            .unwrap_or_else(|| Rc::new(Slot::new()));
+        let responder = Responder {
+            slot: slot.clone(),
+            sent: false,
+        };
+        let res = match self.tx.send((req, responder)).await {
+            // the responder is dropped with the request
+            Ok(()) => poll_fn(|cx| slot.poll(cx)).await,
+            Err(_) => Err(RpcError::Closed),
+        };
+
+        // The responder is gone by now, either used, dropped or given back
+        // with the send error. A cancelled call never gets here, its slot is
+        // freed with the responder instead.
+        if Rc::strong_count(&slot) == 1 {
+            slot.state.set(State::Waiting);
+            slot.waker.take();
+            self.pool.borrow_mut().push(slot);
+        }
+        res
+    }
+
+    /// Returns `true` if the server has been dropped or closed.
+    pub fn is_closed(&self) -> bool {
+        self.tx.is_closed()
+    }
+
+    /// Completes when the server has been dropped or closed.
+    pub async fn closed(&self) {
+        self.tx.closed().await
+    }
+}
+
+impl<Req, Resp> Clone for Client<Req, Resp> {
+    /// The clone does not share the pool of response slots.
+    fn clone(&self) -> Self {
+        Self {
+            tx: self.tx.clone(),
+            pool: RefCell::new(Vec::new()),
+        }
+    }
+}
+
+impl<Req, Resp> Server<Req, Resp> {
+    /// Receives the next request with its responder. Returns `None` once all
+    /// clients have been dropped.
+    pub async fn recv(&mut self) -> Option<(Req, Responder<Resp>)> {
+        self.rx.recv().await
+    }
+
+    pub fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<Option<(Req, Responder<Resp>)>> {
+        self.rx.poll_recv(cx)
+    }
+
+    /// Stops accepting requests, later calls fail with `RpcError::Closed`.
+    /// Requests already queued can still be received.
+    pub fn close(&self) {
+        self.rx.close()
+    }
+}
+
+impl<Resp> Responder<Resp> {
+    /// Sends the response. Fails and gives it back if the call has been
+    /// cancelled.
+    pub fn send(mut self, resp: Resp) -> Result<(), Resp> {
+        if self.is_closed() {
+            return Err(resp);
+        }
+        *self.slot.value.borrow_mut() = Some(resp);
+        self.sent = true;
+        self.slot.complete(State::Sent);
+        Ok(())
+    }
+
+    /// Returns `true` if the call has been cancelled and nobody waits for the
+    /// response anymore.
+    pub fn is_closed(&self) -> bool {
+        Rc::strong_count(&self.slot) == 1
+    }
+}
+
+impl<Resp> Drop for Responder<Resp> {
+    fn drop(&mut self) {
+        if !self.sent {
+            self.slot.complete(State::Dropped);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{channel, RpcError};
+
+    #[monoio::test]
+    async fn test_rpc() {
+        let (client, mut server) = channel::<u32, u32>(4);
+        let join = monoio::spawn(async move {
+            while let Some((req, responder)) = server.recv().await {
+                if req == 0 {
+                    // dropped without response
+                    continue;
+                }
+                responder.send(req * 2).unwrap();
+            }
+        });
+
+        assert_eq!(client.call(1).await, Ok(2));
+        assert_eq!(client.call(21).await, Ok(42));
+        // the slot has been reused
+        assert_eq!(client.pool.borrow().len(), 1);
+        assert_eq!(client.call(0).await, Err(RpcError::Dropped));
+        assert_eq!(client.call(3).await, Ok(6));
+        assert_eq!(client.pool.borrow().len(), 1);
+
+        let client2 = client.clone();
+        drop(client);
+        assert_eq!(client2.call(4).await, Ok(8));
+        drop(client2);
+        join.await;
+    }
+
+    #[monoio::test]
+    async fn test_rpc_server_closed() {
+        let (client, server) = channel::<u32, u32>(1);
+        drop(server);
+        assert!(client.is_closed());
+        client.closed().await;
+        assert_eq!(client.call(1).await, Err(RpcError::Closed));
+    }
+}
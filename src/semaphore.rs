@@ -583,6 +583,10 @@ impl Semaphore {
         self.0.available_permits()
     }
 
+    pub(crate) fn inner(&self) -> &Inner {
+        &self.0
+    }
+
     /// Adds `n` new permits to the semaphore.
     ///
     /// The maximum number of permits is `usize::MAX >> 3`, and this function will panic if the limit is exceeded.
@@ -0,0 +1,73 @@
+use super::chan::{self, SendError, TryRecvError};
+use futures_lite::future::poll_fn;
+use std::task::{Context, Poll};
+
+pub struct Tx<T>(chan::Tx<T>);
+
+pub struct Rx<T>(chan::Rx<T>);
+
+pub fn channel<T>(buffer: usize) -> (Tx<T>, Rx<T>) {
+    let (tx, rx) = chan::channel(buffer);
+    (Tx(tx), Rx(rx))
+}
+
+impl<T> Tx<T> {
+    pub async fn send(&mut self, value: T) -> Result<(), SendError<T>> {
+        let mut value = Some(value);
+        poll_fn(|cx| self.0.poll_send(cx, &mut value)).await
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.0.is_closed()
+    }
+
+    pub fn hint(&self) -> usize {
+        self.0.hint()
+    }
+}
+
+impl<T> Rx<T> {
+    pub async fn recv(&mut self) -> Option<T> {
+        poll_fn(|cx| self.poll_recv(cx)).await
+    }
+
+    pub fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        self.0.recv(cx)
+    }
+
+    pub fn try_recv(&mut self) -> Result<T, TryRecvError> {
+        self.0.try_recv()
+    }
+
+    pub fn close(&self) {
+        self.0.close()
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.0.is_closed()
+    }
+
+    pub fn hint(&self) -> usize {
+        self.0.hint()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::channel;
+
+    #[monoio::test]
+    async fn test_bounded_channel() {
+        let (mut tx, mut rx) = channel(1);
+        let join = monoio::spawn(async move {
+            for i in 0..64 {
+                tx.send(i).await.unwrap();
+            }
+        });
+        for i in 0..64 {
+            assert_eq!(rx.recv().await, Some(i));
+        }
+        join.await;
+        assert_eq!(rx.recv().await, None);
+    }
+}
@@ -0,0 +1,231 @@
+use std::{
+    cell::{Cell, UnsafeCell},
+    rc::Rc,
+    task::{Context, Poll, Waker},
+};
+
+use crate::mpsc::block::Queue;
+
+pub use crate::mpsc::{SendError, TryRecvError};
+
+pub(crate) fn channel<T>(capacity: usize) -> (Tx<T>, Rx<T>) {
+    let chan = Rc::new(Chan::new(capacity));
+    let tx = Tx { chan: chan.clone() };
+    let rx = Rx { chan };
+    (tx, rx)
+}
+
+/// Channel state shared by the only producer and the only consumer.
+///
+/// Since there is exactly one `Tx`, there is no sender count to maintain and
+/// at most one producer can be waiting, so both wakers live in fixed slots.
+/// The queue is only touched by short non-reentrant sections of the two
+/// handles, so it needs no `RefCell` borrow tracking either.
+pub(crate) struct Chan<T> {
+    queue: UnsafeCell<Queue<T>>,
+    capacity: usize,
+    rx_waker: Cell<Option<Waker>>,
+    tx_waker: Cell<Option<Waker>>,
+    tx_closed: Cell<bool>,
+    rx_closed: Cell<bool>,
+}
+
+impl<T> Chan<T> {
+    fn new(capacity: usize) -> Self {
+        Self {
+            queue: UnsafeCell::new(Queue::new()),
+            capacity,
+            rx_waker: Cell::new(None),
+            tx_waker: Cell::new(None),
+            tx_closed: Cell::new(false),
+            rx_closed: Cell::new(false),
+        }
+    }
+}
+
+impl<T> Drop for Chan<T> {
+    fn drop(&mut self) {
+        let queue = self.queue.get_mut();
+        while !queue.is_empty() {
+            drop(unsafe { queue.pop_unchecked() });
+        }
+        // drop all blocks of queue
+        unsafe { queue.free_blocks() }
+    }
+}
+
+/// Store the waker into the slot unless it already wakes the same task.
+fn register(slot: &Cell<Option<Waker>>, cx: &mut Context<'_>) {
+    match slot.take() {
+        Some(waker) if waker.will_wake(cx.waker()) => slot.set(Some(waker)),
+        _ => slot.set(Some(cx.waker().clone())),
+    }
+}
+
+fn wake(slot: &Cell<Option<Waker>>) {
+    if let Some(waker) = slot.take() {
+        waker.wake();
+    }
+}
+
+pub(crate) struct Tx<T> {
+    chan: Rc<Chan<T>>,
+}
+
+pub(crate) struct Rx<T> {
+    chan: Rc<Chan<T>>,
+}
+
+impl<T> Tx<T> {
+    /// Push the value if there is space, otherwise register the waker.
+    ///
+    /// The value is only taken out of `value` when it has been sent.
+    pub(crate) fn poll_send(
+        &mut self,
+        cx: &mut Context<'_>,
+        value: &mut Option<T>,
+    ) -> Poll<Result<(), SendError<T>>> {
+        if self.chan.rx_closed.get() {
+            let value = value.take().expect("poll_send called after completion");
+            return Poll::Ready(Err(SendError::RxClosed(value)));
+        }
+        if self.hint() >= self.chan.capacity {
+            register(&self.chan.tx_waker, cx);
+            return Poll::Pending;
+        }
+        if let Some(value) = value.take() {
+            self.push(value);
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    // only used by the unbounded channel, which always has spaces
+    pub(crate) fn send(&self, value: T) -> Result<(), SendError<T>> {
+        if self.chan.rx_closed.get() {
+            return Err(SendError::RxClosed(value));
+        }
+        self.push(value);
+        Ok(())
+    }
+
+    fn push(&self, value: T) {
+        unsafe {
+            (*self.chan.queue.get()).push_unchecked(value);
+        }
+        wake(&self.chan.rx_waker);
+    }
+
+    pub(crate) fn is_closed(&self) -> bool {
+        self.chan.rx_closed.get()
+    }
+
+    pub(crate) fn hint(&self) -> usize {
+        unsafe { (*self.chan.queue.get()).len() }
+    }
+}
+
+impl<T> Drop for Tx<T> {
+    fn drop(&mut self) {
+        self.chan.tx_closed.set(true);
+        wake(&self.chan.rx_waker);
+    }
+}
+
+impl<T> Rx<T> {
+    /// Pop a value. The queue reference ends here, so the caller may drop
+    /// the value even if its `Drop` touches this channel.
+    fn pop(&self) -> Option<T> {
+        let queue = unsafe { &mut *self.chan.queue.get() };
+        if queue.is_empty() {
+            return None;
+        }
+        Some(unsafe { queue.pop_unchecked() })
+    }
+
+    pub(crate) fn try_recv(&mut self) -> Result<T, TryRecvError> {
+        if let Some(val) = self.pop() {
+            wake(&self.chan.tx_waker);
+            return Ok(val);
+        }
+        if self.chan.tx_closed.get() || self.chan.rx_closed.get() {
+            Err(TryRecvError::Disconnected)
+        } else {
+            Err(TryRecvError::Empty)
+        }
+    }
+
+    pub(crate) fn recv(&mut self, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        match self.try_recv() {
+            Ok(val) => Poll::Ready(Some(val)),
+            Err(TryRecvError::Empty) => {
+                register(&self.chan.rx_waker, cx);
+                Poll::Pending
+            }
+            Err(_) => Poll::Ready(None),
+        }
+    }
+
+    pub(crate) fn close(&self) {
+        self.chan.rx_closed.set(true);
+        wake(&self.chan.tx_waker);
+    }
+
+    pub(crate) fn is_closed(&self) -> bool {
+        self.chan.rx_closed.get()
+    }
+
+    pub(crate) fn hint(&self) -> usize {
+        unsafe { (*self.chan.queue.get()).len() }
+    }
+}
+
+impl<T> Drop for Rx<T> {
+    fn drop(&mut self) {
+        self.close();
+        // consume all elements
+        while let Some(val) = self.pop() {
+            drop(val);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{channel, Tx};
+    use futures_lite::future::poll_fn;
+    use std::{cell::Cell, rc::Rc};
+
+    #[monoio::test]
+    async fn test_chan() {
+        let (tx, mut rx) = channel::<u32>(usize::MAX);
+        assert!(tx.send(1).is_ok());
+        assert_eq!(poll_fn(|cx| rx.recv(cx)).await, Some(1));
+
+        // close rx
+        rx.close();
+        assert!(tx.is_closed());
+        assert!(tx.send(2).is_err());
+    }
+
+    struct Reentrant(Rc<Tx<Reentrant>>, Rc<Cell<usize>>);
+
+    impl Drop for Reentrant {
+        fn drop(&mut self) {
+            // touches the queue while the receiver drains it
+            self.1.set(self.0.hint());
+            assert!(self.0.is_closed());
+        }
+    }
+
+    #[monoio::test]
+    async fn test_chan_drop_reentrant() {
+        let (tx, rx) = channel(usize::MAX);
+        let tx = Rc::new(tx);
+        let seen = Rc::new(Cell::new(usize::MAX));
+        for _ in 0..2 {
+            assert!(tx.send(Reentrant(tx.clone(), seen.clone())).is_ok());
+        }
+        drop(rx);
+        assert_eq!(seen.get(), 0);
+    }
+}
@@ -0,0 +1,11 @@
+//! Single-producer single-consumer channels.
+//!
+//! Unlike [`mpsc`](crate::mpsc), the sending half can not be cloned, which
+//! lets the channel skip sender reference counting entirely.
+
+mod chan;
+
+pub mod bounded;
+pub mod unbounded;
+
+pub use crate::mpsc::{SendError, TryRecvError};